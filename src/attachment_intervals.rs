@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::time::Duration;
+use polars::prelude::*;
+use crate::gtfs::duration_to_string;
+
+/// A mobile node being attached to or detached from a parent at an emulated point in time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AttachmentChange {
+    pub timestamp: Duration,
+    pub parent_id: u64,
+    pub child_id: u64,
+    pub attached: bool,
+}
+
+/// A period during which a mobile node is attached to a parent, in real schedule time and in emulated time
+#[derive(Clone, Debug, PartialEq)]
+pub struct AttachmentInterval {
+    pub child_id: u64,
+    pub parent_id: u64,
+    /// Offsets from the start time of the schedule
+    pub real_start: Duration,
+    pub real_end: Duration,
+    /// Time of day in the schedule
+    pub real_start_time: String,
+    pub real_end_time: String,
+    pub emulated_start: Duration,
+    pub emulated_end: Duration,
+}
+
+/// Replay the attachment changes starting from the initial (parent, child) pairs. Attachments that are still open at the end
+/// of the emulation end with it. The intervals are ordered by child id and start time. to_real maps an emulated time to the
+/// offset from the start time of the schedule.
+pub fn compute_attachment_intervals(initial_parents: &[(u64, u64)], changes: impl IntoIterator<Item=AttachmentChange>, emulation_end: Duration, start_time: Duration, to_real: impl Fn(Duration) -> Duration) -> Vec<AttachmentInterval> {
+    let mut open: HashMap<(u64, u64), Duration> = initial_parents.iter().map(|(parent_id, child_id)| ((*child_id, *parent_id), Duration::ZERO)).collect();
+    let mut intervals = vec![];
+    let mut end = emulation_end;
+    for change in changes {
+        end = end.max(change.timestamp);
+        let key = (change.child_id, change.parent_id);
+        if change.attached {
+            open.entry(key).or_insert(change.timestamp);
+        } else if let Some(start) = open.remove(&key) {
+            intervals.push((key, start, change.timestamp));
+        }
+    }
+    intervals.extend(open.into_iter().map(|(key, start)| (key, start, end)));
+    intervals.sort_by_key(|((child_id, parent_id), start, _)| (*child_id, *start, *parent_id));
+
+    intervals.into_iter().map(|((child_id, parent_id), start, end)| AttachmentInterval {
+        child_id,
+        parent_id,
+        real_start: to_real(start),
+        real_end: to_real(end),
+        real_start_time: duration_to_string(start_time + to_real(start)),
+        real_end_time: duration_to_string(start_time + to_real(end)),
+        emulated_start: start,
+        emulated_end: end,
+    }).collect()
+}
+
+fn intervals_to_data_frame(intervals: &[AttachmentInterval]) -> PolarsResult<DataFrame> {
+    let millis = |value: fn(&AttachmentInterval) -> Duration| intervals.iter().map(|i| value(i).as_millis() as u64).collect::<Vec<u64>>();
+    df!(
+        "child_id" => intervals.iter().map(|i| i.child_id).collect::<Vec<u64>>(),
+        "parent_id" => intervals.iter().map(|i| i.parent_id).collect::<Vec<u64>>(),
+        "real_start_ms" => millis(|i| i.real_start),
+        "real_end_ms" => millis(|i| i.real_end),
+        "real_start_time" => intervals.iter().map(|i| i.real_start_time.clone()).collect::<Vec<String>>(),
+        "real_end_time" => intervals.iter().map(|i| i.real_end_time.clone()).collect::<Vec<String>>(),
+        "emulated_start_ms" => millis(|i| i.emulated_start),
+        "emulated_end_ms" => millis(|i| i.emulated_end),
+    )
+}
+
+pub fn write_intervals_to_csv(intervals: &[AttachmentInterval], path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut df = intervals_to_data_frame(intervals)?;
+    CsvWriter::new(File::create(path)?).finish(&mut df)?;
+    Ok(())
+}
+
+pub fn write_intervals_to_parquet(intervals: &[AttachmentInterval], path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut df = intervals_to_data_frame(intervals)?;
+    ParquetWriter::new(File::create(path)?).finish(&mut df)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute_attachment_intervals() {
+        let changes = vec![
+            AttachmentChange { timestamp: Duration::from_secs(1), parent_id: 2, child_id: 10, attached: false },
+            AttachmentChange { timestamp: Duration::from_secs(1), parent_id: 3, child_id: 10, attached: true },
+        ];
+        let intervals = compute_attachment_intervals(&[(2, 10), (2, 11)], changes, Duration::from_secs(5), Duration::from_secs(8 * 3600), |t| t * 10);
+        assert_eq!(intervals.len(), 3);
+        assert_eq!((intervals[0].parent_id, intervals[0].emulated_end, intervals[0].real_end), (2, Duration::from_secs(1), Duration::from_secs(10)));
+        assert_eq!((intervals[1].parent_id, intervals[1].emulated_start, intervals[1].emulated_end), (3, Duration::from_secs(1), Duration::from_secs(5)));
+        assert_eq!((intervals[1].real_start_time.as_str(), intervals[1].real_end_time.as_str()), ("08:00:10", "08:00:50"));
+        assert_eq!(intervals[2].child_id, 11);
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::{BTreeSet, VecDeque};
+use crate::geo_utils;
+use serde::{Deserialize, Serialize};
+use crate::nes_simulation::{FixedTopology, LinkAttributes, TopologyLink};
+
+/// The graph used to connect fog nodes with each other
+#[derive(Clone, Debug, PartialEq)]
+pub enum Backhaul {
+    /// Connect the nodes with the minimum spanning tree of their geographic distances
+    MinimumSpanningTree,
+    /// Connect every node to its k closest neighbours
+    NearestNeighbours { k: usize },
+    /// Connect the nodes along the edges of their Delaunay triangulation
+    Delaunay,
+    /// Create one node per (lat, lon) aggregation point and attach every node to its closest aggregation point
+    Star { aggregation_points: Vec<(f64, f64)> },
+}
+
+/// Estimate the latency of a link from its length as base + per_km * distance
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct DistanceLatencyModel {
+    pub base_latency_in_ms: f64,
+    pub latency_per_km_in_ms: f64,
+}
+
+impl DistanceLatencyModel {
+    pub fn latency_in_ms(&self, distance_in_meters: f64) -> f64 {
+        self.base_latency_in_ms + self.latency_per_km_in_ms * distance_in_meters / 1000.0
+    }
+}
+
+fn location(topology: &FixedTopology, node_id: u64) -> (f64, f64) {
+    let coordinates = &topology.nodes[&node_id];
+    (coordinates[1], coordinates[0])
+}
+
+fn minimum_spanning_tree(distances: &[Vec<f64>]) -> Vec<(usize, usize)> {
+    let n = distances.len();
+    let mut edges = vec![];
+    if n == 0 {
+        return edges;
+    }
+    let mut in_tree = vec![false; n];
+    let mut cheapest = vec![(f64::MAX, 0); n];
+    in_tree[0] = true;
+    for j in 1..n {
+        cheapest[j] = (distances[0][j], 0);
+    }
+    for _ in 1..n {
+        let next = (0..n).filter(|j| !in_tree[*j]).min_by(|a, b| cheapest[*a].0.total_cmp(&cheapest[*b].0)).unwrap();
+        in_tree[next] = true;
+        edges.push((cheapest[next].1, next));
+        for j in 0..n {
+            if !in_tree[j] && distances[next][j] < cheapest[j].0 {
+                cheapest[j] = (distances[next][j], next);
+            }
+        }
+    }
+    edges
+}
+
+fn nearest_neighbours(distances: &[Vec<f64>], k: usize) -> Vec<(usize, usize)> {
+    let mut edges = BTreeSet::new();
+    for (i, row) in distances.iter().enumerate() {
+        let mut neighbours: Vec<usize> = (0..row.len()).filter(|j| *j != i).collect();
+        neighbours.sort_by(|a, b| row[*a].total_cmp(&row[*b]));
+        for j in neighbours.into_iter().take(k) {
+            edges.insert((i.min(j), i.max(j)));
+        }
+    }
+    edges.into_iter().collect()
+}
+
+// a circle through three points given by its center and squared radius
+fn circumcircle(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> Option<((f64, f64), f64)> {
+    let d = 2.0 * (a.0 * (b.1 - c.1) + b.0 * (c.1 - a.1) + c.0 * (a.1 - b.1));
+    if d.abs() < f64::EPSILON {
+        return None;
+    }
+    let a2 = a.0 * a.0 + a.1 * a.1;
+    let b2 = b.0 * b.0 + b.1 * b.1;
+    let c2 = c.0 * c.0 + c.1 * c.1;
+    let x = (a2 * (b.1 - c.1) + b2 * (c.1 - a.1) + c2 * (a.1 - b.1)) / d;
+    let y = (a2 * (c.0 - b.0) + b2 * (a.0 - c.0) + c2 * (b.0 - a.0)) / d;
+    Some(((x, y), (a.0 - x).powi(2) + (a.1 - y).powi(2)))
+}
+
+/// Bowyer-Watson triangulation of planar points. Returns the undirected edges of the triangulation.
+fn delaunay(points: &[(f64, f64)]) -> Vec<(usize, usize)> {
+    let n = points.len();
+    if n < 3 {
+        return if n == 2 { vec![(0, 1)] } else { vec![] };
+    }
+    let min_x = points.iter().map(|p| p.0).fold(f64::MAX, f64::min);
+    let max_x = points.iter().map(|p| p.0).fold(f64::MIN, f64::max);
+    let min_y = points.iter().map(|p| p.1).fold(f64::MAX, f64::min);
+    let max_y = points.iter().map(|p| p.1).fold(f64::MIN, f64::max);
+    let size = (max_x - min_x).max(max_y - min_y).max(f64::EPSILON) * 20.0;
+    let mid = ((min_x + max_x) / 2.0, (min_y + max_y) / 2.0);
+
+    //the vertices of a super triangle containing all points are appended after the input points
+    let mut vertices = points.to_vec();
+    vertices.push((mid.0 - size, mid.1 - size));
+    vertices.push((mid.0 + size, mid.1 - size));
+    vertices.push((mid.0, mid.1 + size));
+    let mut triangles = vec![[n, n + 1, n + 2]];
+
+    for i in 0..n {
+        let p = vertices[i];
+        let mut polygon: Vec<(usize, usize)> = vec![];
+        triangles.retain(|t| {
+            let inside = match circumcircle(vertices[t[0]], vertices[t[1]], vertices[t[2]]) {
+                Some((center, radius)) => (p.0 - center.0).powi(2) + (p.1 - center.1).powi(2) < radius,
+                None => false,
+            };
+            if inside {
+                for (a, b) in [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])] {
+                    //edges shared by two removed triangles are not part of the polygon boundary
+                    if let Some(pos) = polygon.iter().position(|e| *e == (b, a) || *e == (a, b)) {
+                        polygon.remove(pos);
+                    } else {
+                        polygon.push((a, b));
+                    }
+                }
+            }
+            !inside
+        });
+        for (a, b) in polygon {
+            triangles.push([a, b, i]);
+        }
+    }
+
+    let mut edges = BTreeSet::new();
+    for t in triangles.iter().filter(|t| t.iter().all(|v| *v < n)) {
+        for (a, b) in [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])] {
+            edges.insert((a.min(b), a.max(b)));
+        }
+    }
+    edges.into_iter().collect()
+}
+
+// orient undirected edges away from a root so that the resulting graph is acyclic. Nodes closer to the root (in hops) become parents,
+// ties are broken by the node index. Every connected component is rooted at its node closest to the centroid of all nodes.
+fn orient_edges(locations: &[(f64, f64)], edges: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let n = locations.len();
+    let mut adjacency: Vec<Vec<usize>> = vec![vec![]; n];
+    for (a, b) in edges {
+        adjacency[*a].push(*b);
+        adjacency[*b].push(*a);
+    }
+    let centroid = (
+        locations.iter().map(|l| l.0).sum::<f64>() / n as f64,
+        locations.iter().map(|l| l.1).sum::<f64>() / n as f64,
+    );
+    let mut by_centrality: Vec<usize> = (0..n).collect();
+    by_centrality.sort_by(|a, b| geo_utils::vincenty_dist_between_coordinates(locations[*a], centroid)
+        .total_cmp(&geo_utils::vincenty_dist_between_coordinates(locations[*b], centroid)));
+
+    let mut level = vec![usize::MAX; n];
+    for root in by_centrality {
+        if level[root] != usize::MAX {
+            continue;
+        }
+        level[root] = 0;
+        let mut queue = VecDeque::from([root]);
+        while let Some(node) = queue.pop_front() {
+            for neighbour in &adjacency[node] {
+                if level[*neighbour] == usize::MAX {
+                    level[*neighbour] = level[node] + 1;
+                    queue.push_back(*neighbour);
+                }
+            }
+        }
+    }
+    edges.iter().map(|(a, b)| if (level[*a], *a) < (level[*b], *b) { (*a, *b) } else { (*b, *a) }).collect()
+}
+
+/// Connect the given nodes of the topology with backhaul links. The links are written into the children of the topology.
+/// If a latency model is given, the estimated latency of every link is added to the links of the topology.
+/// Returns the ids of the aggregation nodes created for a star backhaul.
+pub fn add_backhaul_links(topology: &mut FixedTopology, node_ids: &[u64], backhaul: &Backhaul, aggregation_slots: u16, latency_model: Option<DistanceLatencyModel>) -> Vec<u64> {
+    let mut node_ids = node_ids.to_vec();
+    node_ids.sort();
+    let locations: Vec<(f64, f64)> = node_ids.iter().map(|id| location(topology, *id)).collect();
+    let distances: Vec<Vec<f64>> = locations.iter().map(|a| locations.iter().map(|b| geo_utils::vincenty_dist_between_coordinates(*a, *b)).collect()).collect();
+
+    let mut aggregation_nodes = vec![];
+    let parent_child_pairs: Vec<(u64, u64)> = match backhaul {
+        Backhaul::MinimumSpanningTree => orient_edges(&locations, &minimum_spanning_tree(&distances)).iter().map(|(p, c)| (node_ids[*p], node_ids[*c])).collect(),
+        Backhaul::NearestNeighbours { k } => orient_edges(&locations, &nearest_neighbours(&distances, *k)).iter().map(|(p, c)| (node_ids[*p], node_ids[*c])).collect(),
+        Backhaul::Delaunay => {
+            //project the coordinates onto a plane around the mean latitude before triangulating
+            let mean_lat = locations.iter().map(|l| l.0).sum::<f64>() / locations.len().max(1) as f64;
+            let projected: Vec<(f64, f64)> = locations.iter().map(|(lat, lon)| (lon * mean_lat.to_radians().cos(), *lat)).collect();
+            orient_edges(&locations, &delaunay(&projected)).iter().map(|(p, c)| (node_ids[*p], node_ids[*c])).collect()
+        }
+        Backhaul::Star { aggregation_points } => {
+            let first_id = topology.nodes.keys().max().unwrap() + 1;
+            for (i, (lat, lon)) in aggregation_points.iter().enumerate() {
+                let id = first_id + i as u64;
+                topology.nodes.insert(id, vec![*lon, *lat]);
+                topology.slots.insert(id, aggregation_slots);
+                topology.children.insert(id, vec![]);
+                aggregation_nodes.push(id);
+            }
+            let mut pairs = vec![];
+            for (node_id, node_location) in node_ids.iter().zip(&locations) {
+                let closest = aggregation_points.iter().enumerate()
+                    .min_by(|a, b| geo_utils::vincenty_dist_between_coordinates(*node_location, *a.1).total_cmp(&geo_utils::vincenty_dist_between_coordinates(*node_location, *b.1)));
+                if let Some((i, _)) = closest {
+                    pairs.push((first_id + i as u64, *node_id));
+                }
+            }
+            pairs
+        }
+    };
+
+    println!("Created {} backhaul links", parent_child_pairs.len());
+    for (parent_id, child_id) in parent_child_pairs {
+        topology.children.entry(parent_id).or_default().push(child_id);
+        if let Some(model) = latency_model {
+            let distance = geo_utils::vincenty_dist_between_coordinates(location(topology, parent_id), location(topology, child_id));
+            topology.links.push(TopologyLink {
+                parent_id,
+                child_id,
+                attributes: LinkAttributes {
+                    latency_in_ms: Some(model.latency_in_ms(distance)),
+                    ..Default::default()
+                },
+            });
+        }
+    }
+    aggregation_nodes
+}
+
+/// The nodes of a topology that are not the child of any other node
+pub fn top_level_nodes(topology: &FixedTopology) -> Vec<u64> {
+    let children: BTreeSet<u64> = topology.children.values().flatten().copied().collect();
+    let mut nodes: Vec<u64> = topology.nodes.keys().filter(|id| !children.contains(id)).copied().collect();
+    nodes.sort();
+    nodes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+
+    fn topology_from_locations(locations: &[(f64, f64)]) -> FixedTopology {
+        let mut topology = FixedTopology { nodes: HashMap::new(), slots: HashMap::new(), children: HashMap::new(), links: vec![], root: None };
+        for (i, (lat, lon)) in locations.iter().enumerate() {
+            topology.nodes.insert(i as u64 + 2, vec![*lon, *lat]);
+            topology.slots.insert(i as u64 + 2, 1);
+            topology.children.insert(i as u64 + 2, vec![]);
+        }
+        topology
+    }
+
+    #[test]
+    fn test_minimum_spanning_tree_is_a_tree() {
+        let locations = [(52.50, 13.40), (52.51, 13.40), (52.52, 13.41), (52.50, 13.45), (52.53, 13.38)];
+        let mut topology = topology_from_locations(&locations);
+        let nodes = top_level_nodes(&topology);
+        add_backhaul_links(&mut topology, &nodes, &Backhaul::MinimumSpanningTree, 1, None);
+        assert_eq!(topology.children.values().map(|c| c.len()).sum::<usize>(), locations.len() - 1);
+        assert_eq!(top_level_nodes(&topology).len(), 1);
+    }
+
+    #[test]
+    fn test_delaunay_of_square() {
+        let edges = delaunay(&[(0.0, 0.0), (1.0, 0.0), (1.0, 1.1), (0.0, 1.0)]);
+        //four sides and one diagonal
+        assert_eq!(edges.len(), 5);
+    }
+
+    #[test]
+    fn test_star_with_latency() {
+        let locations = [(52.50, 13.40), (52.51, 13.40), (52.60, 13.40)];
+        let mut topology = topology_from_locations(&locations);
+        let nodes = top_level_nodes(&topology);
+        let model = DistanceLatencyModel { base_latency_in_ms: 1.0, latency_per_km_in_ms: 0.005 };
+        let aggregation = add_backhaul_links(&mut topology, &nodes, &Backhaul::Star { aggregation_points: vec![(52.505, 13.40), (52.60, 13.41)] }, 10, Some(model));
+        assert_eq!(aggregation, vec![5, 6]);
+        assert_eq!(topology.children[&5], vec![2, 3]);
+        assert_eq!(topology.children[&6], vec![4]);
+        assert_eq!(topology.links.len(), 3);
+        assert!(topology.links.iter().all(|l| l.attributes.latency_in_ms.unwrap() > 1.0));
+    }
+}
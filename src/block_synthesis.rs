@@ -0,0 +1,85 @@
+use std::time::Duration;
+use crate::geo_utils::vincenty_dist_between_coordinates;
+use crate::gtfs::{parse_duration, Stop, TripSchedule};
+
+/// How trips without a block id are assigned to vehicles
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlockSynthesis {
+    /// Every trip is run by its own vehicle
+    PerTrip,
+    /// A vehicle continues with the next trip of the route if it starts within the layover time at the stop where the vehicle
+    /// ended, or at a stop at most max_stop_distance meters away
+    Chain { max_layover: Duration, max_stop_distance: f64 },
+}
+
+// a vehicle and the time and stop at which it finished its last trip
+struct VehicleRun {
+    trips: Vec<TripSchedule>,
+    end: Duration,
+    last_stop: Stop,
+}
+
+fn first_departure(trip: &TripSchedule) -> Duration {
+    parse_duration(&trip.stops.first().unwrap().departure_time).unwrap()
+}
+
+fn last_arrival(trip: &TripSchedule) -> Duration {
+    parse_duration(&trip.stops.last().unwrap().arrival_time).unwrap()
+}
+
+/// Group the trips of a single route into vehicle runs. Returns the block id of every run with its trips in the order they are
+/// served. Trips without stops are dropped. With chaining, trips are assigned in the order of their departure to the vehicle
+/// with the shortest compatible layover.
+pub fn synthesize_blocks(route_id: &str, trips: Vec<TripSchedule>, synthesis: BlockSynthesis) -> Vec<(String, Vec<TripSchedule>)> {
+    let mut trips: Vec<TripSchedule> = trips.into_iter().filter(|t| !t.stops.is_empty()).collect();
+    trips.sort_by_key(|t| (first_departure(t), t.trip_id.clone()));
+
+    let mut runs: Vec<VehicleRun> = vec![];
+    for trip in trips {
+        let start = first_departure(&trip);
+        let first_stop = trip.stops.first().unwrap();
+        let previous_run = match synthesis {
+            BlockSynthesis::PerTrip => None,
+            BlockSynthesis::Chain { max_layover, max_stop_distance } => runs.iter_mut()
+                .filter(|run| run.end <= start && start - run.end <= max_layover)
+                .filter(|run| run.last_stop.stop_id == first_stop.stop_id
+                    || vincenty_dist_between_coordinates((run.last_stop.lat, run.last_stop.lon), (first_stop.lat, first_stop.lon)) <= max_stop_distance)
+                .max_by_key(|run| run.end),
+        };
+        let end = last_arrival(&trip);
+        let last_stop = trip.stops.last().unwrap().clone();
+        match previous_run {
+            Some(run) => {
+                run.end = end;
+                run.last_stop = last_stop;
+                run.trips.push(trip);
+            }
+            None => runs.push(VehicleRun { trips: vec![trip], end, last_stop }),
+        }
+    }
+
+    println!("Assigned the trips of route {} to {} vehicles", route_id, runs.len());
+    runs.into_iter().map(|run| (format!("synthetic_{}_{}", route_id, run.trips[0].trip_id), run.trips)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chain_trips() {
+        let trips = vec![
+            TripSchedule::test("out1", &[("A", 13.40, "08:00:00"), ("B", 13.45, "08:20:00")]),
+            TripSchedule::test("back1", &[("B", 13.45, "08:25:00"), ("A", 13.40, "08:45:00")]),
+            TripSchedule::test("out2", &[("A", 13.40, "08:10:00"), ("B", 13.45, "08:30:00")]),
+            //too long after the arrival of out2
+            TripSchedule::test("back2", &[("B", 13.45, "09:00:00"), ("A", 13.40, "09:20:00")]),
+        ];
+        let chain = BlockSynthesis::Chain { max_layover: Duration::from_secs(600), max_stop_distance: 100.0 };
+        let blocks = synthesize_blocks("100", trips.clone(), chain);
+        let trip_ids: Vec<Vec<&str>> = blocks.iter().map(|(_, trips)| trips.iter().map(|t| t.trip_id.as_str()).collect()).collect();
+        assert_eq!(trip_ids, vec![vec!["out1", "back1"], vec!["out2"], vec!["back2"]]);
+        assert_eq!(blocks[0].0, "synthetic_100_out1");
+        assert_eq!(synthesize_blocks("100", trips, BlockSynthesis::PerTrip).len(), 4);
+    }
+}
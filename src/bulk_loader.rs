@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use rusqlite::{named_params, Connection};
+use crate::gtfs::{ShapePoint, Stop, TripSchedule};
+
+/// A trip of the selected lines on the selected service day
+#[derive(Clone, Debug)]
+pub struct TripInfo {
+    pub trip_id: String,
+    /// Short name of the route
+    pub route_id: String,
+    pub block_id: Option<String>,
+    /// Basic or extended GTFS route type
+    pub route_type: i64,
+}
+
+/// All trips of the selected lines on the selected service day with their stops and shapes
+#[derive(Clone, Debug)]
+pub struct ScheduleSelection {
+    pub trips: Vec<TripInfo>,
+    pub schedules: HashMap<String, TripSchedule>,
+}
+
+impl ScheduleSelection {
+    /// The schedule of a trip, trips that could not be loaded have no stops and no shape
+    pub fn take_schedule(&mut self, trip_id: &str) -> TripSchedule {
+        self.schedules.remove(trip_id).unwrap_or_else(|| TripSchedule { trip_id: trip_id.to_string(), stops: vec![], shape_points: vec![] })
+    }
+}
+
+// the trips of the lines on the first date of the feed that falls on the day of the week
+fn route_and_day_filter(line_names: &[String]) -> String {
+    let line_names = line_names.iter().map(|line_name| format!("'{}'", line_name))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("{}{}{}", "FROM routes, trips, calendar_dates \
+                       WHERE routes.route_id=trips.route_id \
+                       AND trips.service_id=calendar_dates.service_id \
+                       AND routes.route_short_name in (", line_names, ") \
+                       AND calendar_dates.date=( \
+                             SELECT min(calendar_dates.date) \
+                             FROM calendar_dates \
+                             WHERE strftime('%w',calendar_dates.date) =:day_of_the_week)")
+}
+
+/// Load the trips, stop times, stops and shapes of the selected lines with one query per table instead of one query per trip
+pub fn load_schedules(db: &Connection, line_names: &[String], day_of_the_week: &str) -> rusqlite::Result<ScheduleSelection, Box<dyn std::error::Error>> {
+    let filter = route_and_day_filter(line_names);
+
+    let mut stmt = db.prepare(&format!("SELECT DISTINCT trips.trip_id, routes.route_short_name, trips.block_id, trips.shape_id, routes.route_type {} ORDER BY trips.trip_id", filter))?;
+    let trips_and_shapes = stmt.query_map(named_params! {":day_of_the_week": day_of_the_week}, |row| {
+        let block_id: Option<String> = row.get(2)?;
+        let trip = TripInfo {
+            trip_id: row.get(0)?,
+            route_id: row.get(1)?,
+            block_id: block_id.filter(|b| !b.is_empty()),
+            route_type: row.get(4)?,
+        };
+        Ok((trip, row.get::<usize, Option<String>>(3)?))
+    })?.collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut stmt = db.prepare(&format!("SELECT stop_times.trip_id, stops.stop_id, arrival_time, departure_time, stop_name, stop_lat, stop_lon, stop_times.shape_dist_traveled, stop_sequence \
+                                        FROM stop_times JOIN stops ON stops.stop_id=stop_times.stop_id \
+                                        WHERE stop_times.trip_id IN (SELECT trips.trip_id {}) \
+                                        ORDER BY stop_times.trip_id, stop_sequence", filter))?;
+    let stops = stmt.query_map(named_params! {":day_of_the_week": day_of_the_week}, |row| {
+        Ok(Stop {
+            trip_id: row.get(0)?,
+            stop_id: row.get(1)?,
+            arrival_time: row.get(2)?,
+            departure_time: row.get(3)?,
+            stop_name: row.get(4)?,
+            lat: row.get(5)?,
+            lon: row.get(6)?,
+            stop_sequence: row.get(8)?,
+            shape_dist_traveled: row.get(7)?,
+            match_distance: None,
+        })
+    })?;
+    let mut stops_per_trip: HashMap<String, Vec<Stop>> = HashMap::new();
+    for stop in stops {
+        let stop = stop?;
+        stops_per_trip.entry(stop.trip_id.clone()).or_default().push(stop);
+    }
+
+    let mut stmt = db.prepare(&format!("SELECT shape_id, shape_pt_lat, shape_pt_lon, shape_pt_sequence, shape_dist_traveled FROM shapes \
+                                        WHERE shape_id IN (SELECT trips.shape_id {}) \
+                                        ORDER BY shape_id, shape_pt_sequence", filter))?;
+    let shape_points = stmt.query_map(named_params! {":day_of_the_week": day_of_the_week}, |row| {
+        Ok(ShapePoint {
+            shape_id: row.get(0)?,
+            shape_pt_lat: row.get(1)?,
+            shape_pt_lon: row.get(2)?,
+            shape_pt_sequence: row.get(3)?,
+            shape_dist_traveled: row.get(4)?,
+            time: None,
+            synthetic: false,
+        })
+    })?;
+    let mut shapes: HashMap<String, Vec<ShapePoint>> = HashMap::new();
+    for shape_point in shape_points {
+        let shape_point = shape_point?;
+        shapes.entry(shape_point.shape_id.clone()).or_default().push(shape_point);
+    }
+
+    let schedules = trips_and_shapes.iter().map(|(trip, shape_id)| {
+        let shape_points = shape_id.as_ref().and_then(|shape_id| shapes.get(shape_id)).cloned().unwrap_or_default();
+        let stops = stops_per_trip.remove(&trip.trip_id).unwrap_or_default();
+        (trip.trip_id.clone(), TripSchedule { trip_id: trip.trip_id.clone(), stops, shape_points })
+    }).collect();
+    let trips: Vec<TripInfo> = trips_and_shapes.into_iter().map(|(trip, _)| trip).collect();
+    println!("Loaded {} trips with {} shapes", trips.len(), shapes.len());
+
+    Ok(ScheduleSelection { trips, schedules })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_schedules() {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch("
+            CREATE TABLE routes(route_id TEXT, route_short_name TEXT, route_type INTEGER);
+            CREATE TABLE trips(trip_id TEXT, route_id TEXT, service_id TEXT, block_id TEXT, shape_id TEXT);
+            CREATE TABLE calendar_dates(service_id TEXT, date DATE);
+            CREATE TABLE stop_times(trip_id TEXT, stop_id TEXT, arrival_time TEXT, departure_time TEXT, stop_sequence INTEGER, shape_dist_traveled NUMERIC);
+            CREATE TABLE stops(stop_id TEXT, stop_name TEXT, stop_lat REAL, stop_lon REAL);
+            CREATE TABLE shapes(shape_id TEXT, shape_pt_lat REAL, shape_pt_lon REAL, shape_pt_sequence INTEGER, shape_dist_traveled NUMERIC);
+            INSERT INTO routes VALUES ('r1', 'S41', 109), ('r2', 'S42', 109);
+            INSERT INTO calendar_dates VALUES ('monday', '2024-01-08'), ('tuesday', '2024-01-09');
+            INSERT INTO trips VALUES ('t1', 'r1', 'monday', 'b1', 'sh1'), ('t2', 'r1', 'monday', '', NULL), ('t3', 'r1', 'tuesday', 'b1', 'sh1'), ('t4', 'r2', 'monday', 'b2', 'sh1');
+            INSERT INTO stops VALUES ('A', 'Stop A', 52.5, 13.4), ('B', 'Stop B', 52.5, 13.5);
+            INSERT INTO stop_times VALUES ('t1', 'B', '08:10:00', '08:10:00', 2, NULL), ('t1', 'A', '08:00:00', '08:00:00', 1, NULL), ('t2', 'A', '09:00:00', '09:00:00', 1, NULL);
+            INSERT INTO shapes VALUES ('sh1', 52.5, 13.5, 2, NULL), ('sh1', 52.5, 13.4, 1, NULL);
+        ").unwrap();
+        let mut selection = load_schedules(&db, &["S41".to_string()], "1").unwrap();
+        assert_eq!(selection.trips.iter().map(|t| (t.trip_id.as_str(), t.block_id.as_deref())).collect::<Vec<_>>(), vec![("t1", Some("b1")), ("t2", None)]);
+        let t1 = selection.take_schedule("t1");
+        assert_eq!(t1.stops.iter().map(|s| s.stop_id.as_str()).collect::<Vec<_>>(), vec!["A", "B"]);
+        assert_eq!(t1.shape_points.iter().map(|p| p.shape_pt_sequence).collect::<Vec<_>>(), vec![1, 2]);
+        assert!(selection.take_schedule("t2").shape_points.is_empty());
+    }
+}
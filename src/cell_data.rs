@@ -1,4 +1,6 @@
-use std::collections::{HashMap};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
 use std::ptr::addr_of_mut;
 use std::thread::park_timeout;
 use polars::prelude::*;
@@ -6,6 +8,7 @@ use polars_plan::plans::lit;
 use geojson::{Feature, FeatureCollection, GeoJson, Geometry, Value};
 use polars::datatypes::DataType;
 use polars_plan::prelude::col;
+use serde::{Deserialize, Serialize};
 use crate::geo_utils;
 use crate::gtfs::{get_shape_points_from_trips, parse_duration, partial_trips_to_feature_collection, PartialBlock, ShapePoint};
 
@@ -35,22 +38,98 @@ pub fn read_cell_data_csv(file_path: &str) -> PolarsResult<DataFrame, > {
         .finish()
 }
 
+/// The radio access technology of a cell as named in OpenCelliD
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Radio {
+    GSM,
+    UMTS,
+    CDMA,
+    LTE,
+    NR,
+}
+
+impl FromStr for Radio {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "GSM" => Ok(Radio::GSM),
+            "UMTS" => Ok(Radio::UMTS),
+            "CDMA" => Ok(Radio::CDMA),
+            "LTE" => Ok(Radio::LTE),
+            "NR" => Ok(Radio::NR),
+            _ => Err(format!("Unknown radio type {}", s)),
+        }
+    }
+}
+
+impl Radio {
+    /// The mobile network generation of the radio type. The declaration order of the variants does not reflect it, CDMA is a second generation technology.
+    pub fn generation(&self) -> u8 {
+        match self {
+            Radio::GSM | Radio::CDMA => 2,
+            Radio::UMTS => 3,
+            Radio::LTE => 4,
+            Radio::NR => 5,
+        }
+    }
+}
+
+impl fmt::Display for Radio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Globally unique identity of a radio cell. The area code is the TAC for LTE and NR cells and the LAC otherwise.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CellGlobalId {
+    pub radio: Radio,
+    pub mcc: u64,
+    pub mnc: u64,
+    pub area_code: u64,
+    pub cell_id: u64,
+}
+
+impl fmt::Display for CellGlobalId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}-{}-{}-{}", self.radio, self.mcc, self.mnc, self.area_code, self.cell_id)
+    }
+}
+
 //todo: move to additional sim_data module
 #[derive(Debug, Clone)]
 pub struct TripAndCellData {
     pub trip: PartialBlock,
-    //todo: make typedefs for the identifiers
-    pub cell_data: HashMap<(String, u64), (u64, u64)>,
+    //maps (shape id, shape point sequence) to the cell serving that point
+    pub cell_data: HashMap<(String, u64), CellGlobalId>,
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MultiTripAndCellData {
     pub trips: HashMap<String, Vec<TripAndCellData>>,
-    pub radio_cells: HashMap<(u64, u64), RadioCell>,
+    pub radio_cells: HashMap<CellGlobalId, RadioCell>,
 }
 
 impl MultiTripAndCellData {
+    /// Keep the given fraction of the vehicles, spread evenly over the vehicles sorted by block id. The radio cells are kept.
+    pub fn sample_vehicles(&self, fraction: f64) -> Self {
+        let mut block_ids: Vec<&String> = self.trips.values().flatten().map(|t| &t.trip.block_id).collect();
+        block_ids.sort();
+        let kept: HashSet<&String> = block_ids.iter().enumerate()
+            .filter(|(i, _)| ((*i + 1) as f64 * fraction).floor() > (*i as f64 * fraction).floor())
+            .map(|(_, id)| *id)
+            .collect();
+        let trips = self.trips.iter()
+            .map(|(route, trips)| (route.clone(), trips.iter().filter(|t| kept.contains(&t.trip.block_id)).cloned().collect::<Vec<_>>()))
+            .filter(|(_, trips)| !trips.is_empty())
+            .collect();
+        MultiTripAndCellData {
+            trips,
+            radio_cells: self.radio_cells.clone(),
+        }
+    }
 
     pub fn to_geojson(&self) -> GeoJson {
         let features = self.to_features();
@@ -69,8 +148,8 @@ impl MultiTripAndCellData {
             for shape_point in trip.trip.shape_points.iter() {
                 let mut line = vec![];
                 let shape_id = &shape_point.shape_id;
-                if let Some((tower_id, mnc)) = trip.cell_data.get(&(shape_id.clone(), shape_point.shape_pt_sequence)) {
-                    if let Some(tower) = self.radio_cells.get(&(*tower_id, *mnc)) {
+                if let Some(cell_id) = trip.cell_data.get(&(shape_id.clone(), shape_point.shape_pt_sequence)) {
+                    if let Some(tower) = self.radio_cells.get(cell_id) {
                         line.push(vec![tower.lon, tower.lat]);
                     }
                 }
@@ -98,7 +177,32 @@ impl MultiTripAndCellData {
 }
 
 
-pub fn get_closest_cells_from_csv(file_path: &str, radio: &str, mcc: u32, mncs: &Vec<u32>, start_time: u64, updated: u64, sample_count: u64, trips: &[PartialBlock]) -> MultiTripAndCellData {
+/// The cells of the OpenCelliD data that are used: cells of the radio type and networks that were created after the start time,
+/// updated after the update time and measured more often than the sample count
+pub struct CellFilter {
+    pub radio: String,
+    pub mcc: u32,
+    pub mncs: Vec<u32>,
+    pub start_time: u64,
+    pub updated: u64,
+    pub sample_count: u64,
+}
+
+pub fn get_closest_cells_from_csv(file_path: &str, filter: &CellFilter, trips: &[PartialBlock]) -> MultiTripAndCellData {
+    let cell_data = read_filtered_cell_data(file_path, filter);
+    get_closest_cells(&cell_data, trips)
+}
+
+/// Read the cell data and apply the filters once, so that the cells of several sets of trips can be found without reading the file again
+pub fn read_filtered_cell_data(file_path: &str, filter: &CellFilter) -> DataFrame {
+    let df = read_cell_data_csv(file_path).unwrap();
+    println!("Read cell data");
+    let filtered = filter_cell_data(&df, &filter.radio, filter.mcc, &filter.mncs, filter.start_time, filter.updated, filter.sample_count).unwrap();
+    println!("Filter cell tower");
+    filtered
+}
+
+pub fn get_closest_cells(cell_data: &DataFrame, trips: &[PartialBlock]) -> MultiTripAndCellData {
     let mut towers = HashMap::new();
 
     println!("get shapes");
@@ -107,8 +211,7 @@ pub fn get_closest_cells_from_csv(file_path: &str, radio: &str, mcc: u32, mncs:
     println!("got shapes");
 
     println!("get cell");
-    //read and filter cell data
-    add_cell_data(file_path, radio, mcc, mncs, start_time, updated, sample_count, &shape_points, &mut towers);
+    find_towers_in_range(cell_data.clone(), &shape_points, &mut towers);
     println!("got cell");
 
     // let mut trips_and_cells = vec![];
@@ -155,15 +258,43 @@ pub fn filter_cell_data(df: &DataFrame, radio: &str, mcc: u32, mncs: &Vec<u32>,
 pub struct RadioCell {
     pub lat: f64,
     pub lon: f64,
-    pub id: u64,
-    range: f64,
-    pub mnc: u64,
+    pub id: CellGlobalId,
+    pub range: f64,
 }
 
 impl RadioCell {
+    // read the cell in row i of an OpenCelliD data frame
+    pub(crate) fn from_row(df: &DataFrame, i: usize) -> RadioCell {
+        let radio = df.column("radio").unwrap().str().unwrap().get(i).unwrap();
+        let lat = df.column("lat").unwrap().f64().unwrap().get(i).unwrap();
+        let lon = df.column("lon").unwrap().f64().unwrap().get(i).unwrap();
+        let cid = df.column("cid").unwrap().u64().unwrap().get(i).unwrap();
+        let range = df.column("range").unwrap().f64().unwrap().get(i).unwrap();
+        let mcc = df.column("mcc").unwrap().u64().unwrap().get(i).unwrap();
+        let mnc = df.column("mnc").unwrap().u64().unwrap().get(i).unwrap();
+        let tac = df.column("tac").unwrap().u64().unwrap().get(i).unwrap();
+        RadioCell {
+            lat,
+            lon,
+            id: CellGlobalId {
+                radio: radio.parse().unwrap(),
+                mcc,
+                mnc,
+                area_code: tac,
+                cell_id: cid,
+            },
+            range,
+        }
+    }
+
     fn to_feature(&self) -> geojson::Feature {
         let mut properties = geojson::JsonObject::new();
-        properties.insert("id".to_string(), serde_json::Value::Number(self.id.into()));
+        properties.insert("id".to_string(), serde_json::Value::Number(self.id.cell_id.into()));
+        properties.insert("radio".to_string(), serde_json::Value::String(self.id.radio.to_string()));
+        properties.insert("mcc".to_string(), serde_json::Value::Number(self.id.mcc.into()));
+        properties.insert("mnc".to_string(), serde_json::Value::Number(self.id.mnc.into()));
+        properties.insert("area_code".to_string(), serde_json::Value::Number(self.id.area_code.into()));
+        properties.insert("cell_global_id".to_string(), serde_json::Value::String(self.id.to_string()));
         properties.insert("marker-color".to_string(), "#673AB7".into());
         properties.insert("range".to_string(), serde_json::Value::String(self.range.to_string()));
         geojson::Feature {
@@ -176,7 +307,7 @@ impl RadioCell {
     }
 }
 
-pub fn find_towers_in_range(df: DataFrame, shape_points: &Vec<ShapePoint>, towers: &mut HashMap<(u64, u64), RadioCell>) {
+pub fn find_towers_in_range(df: DataFrame, shape_points: &Vec<ShapePoint>, towers: &mut HashMap<CellGlobalId, RadioCell>) {
 
     let mut closest_tower:HashMap<(&String,u64), RadioCell> = HashMap::new();
     let total = shape_points.len();
@@ -199,47 +330,35 @@ pub fn find_towers_in_range(df: DataFrame, shape_points: &Vec<ShapePoint>, tower
             //todo: find more effiient way of iterating
             // https://users.rust-lang.org/t/using-for-loop-on-a-polars-dataframe/101819
             for i in 0..df.height() {
-                let lat = df.column("lat").unwrap().f64().unwrap().get(i).unwrap();
-                let lon = df.column("lon").unwrap().f64().unwrap().get(i).unwrap();
-                let id = df.column("cid").unwrap().u64().unwrap().get(i).unwrap();
-                let range = df.column("range").unwrap().f64().unwrap().get(i).unwrap();
-                let mnc = df.column("mnc").unwrap().u64().unwrap().get(i).unwrap();
+                let cell = RadioCell::from_row(&df, i);
                 //find the vincenty distance
-                let distance = geo_utils::vincenty_dist_between_coordinates((lat, lon), (point.shape_pt_lat, point.shape_pt_lon));
+                let distance = geo_utils::vincenty_dist_between_coordinates((cell.lat, cell.lon), (point.shape_pt_lat, point.shape_pt_lon));
                 if distance < min_distance {
                     min_distance = distance;
-                    optional_closest = Some(RadioCell {
-                        lat,
-                        lon,
-                        id,
-                        range,
-                        mnc,
-                    });
+                    optional_closest = Some(cell);
                 }
             }
         }
         if optional_closest.is_some() {
             let closest = optional_closest.unwrap();
             closest_tower.entry(point_id).or_insert_with(|| closest.clone());
-            let tower_identifier = (closest.id, closest.mnc);
-            towers.insert(tower_identifier, closest);
+            towers.insert(closest.id, closest);
         }
     }
 }
 
 // read cell data, apply filters and find closest towers to a shape point
 //todo: check which params can be reference instead of owned vals
-pub fn find_closest_towers(shape_points: &Vec<ShapePoint>, towers: &mut HashMap<(u64, u64), RadioCell>, point_id_to_towers: &mut HashMap<(String, u64), (u64, u64)>) {
+pub fn find_closest_towers(shape_points: &Vec<ShapePoint>, towers: &mut HashMap<CellGlobalId, RadioCell>, point_id_to_towers: &mut HashMap<(String, u64), CellGlobalId>) {
     for point in shape_points {
         let shape_point_identifier = (point.shape_id.clone(), point.shape_pt_sequence);
         if let Some(closest) = find_closest_tower_from_map(towers, point) {
-            let tower_identifier = (closest.id, closest.mnc);
-            point_id_to_towers.insert(shape_point_identifier, tower_identifier);
+            point_id_to_towers.insert(shape_point_identifier, closest.id);
         }
     }
 }
 
-pub fn add_cell_data(file_path: &str, radio: &str, mcc: u32, mncs: &Vec<u32>, start_time: u64, updated: u64, sample_count: u64, shape_points: &Vec<ShapePoint>, towers: &mut HashMap<(u64, u64), RadioCell>) {
+pub fn add_cell_data(file_path: &str, radio: &str, mcc: u32, mncs: &Vec<u32>, start_time: u64, updated: u64, sample_count: u64, shape_points: &Vec<ShapePoint>, towers: &mut HashMap<CellGlobalId, RadioCell>) {
     let df = read_cell_data_csv(file_path).unwrap();
     println!("Read cell data");
     let filtered = filter_cell_data(&df, radio, mcc, &mncs, start_time, updated, sample_count).unwrap();
@@ -251,7 +370,7 @@ pub fn add_cell_data(file_path: &str, radio: &str, mcc: u32, mncs: &Vec<u32>, st
 
 
 
-fn find_closest_tower_from_map(id_to_cell: &mut HashMap<(u64, u64), RadioCell>, point: &ShapePoint) -> Option<RadioCell> {
+fn find_closest_tower_from_map(id_to_cell: &mut HashMap<CellGlobalId, RadioCell>, point: &ShapePoint) -> Option<RadioCell> {
     //iterator over the rows
     let mut closest = None;
     let mut min_distance = f64::MAX;
@@ -282,22 +401,12 @@ fn find_closest_tower(df: &DataFrame, point: &ShapePoint) -> Option<RadioCell> {
     //todo: find more effiient way of iterating
     // https://users.rust-lang.org/t/using-for-loop-on-a-polars-dataframe/101819
     for i in 0..df.height() {
-        let lat = df.column("lat").unwrap().f64().unwrap().get(i).unwrap();
-        let lon = df.column("lon").unwrap().f64().unwrap().get(i).unwrap();
-        let id = df.column("cid").unwrap().u64().unwrap().get(i).unwrap();
-        let range = df.column("range").unwrap().f64().unwrap().get(i).unwrap();
-        let mnc = df.column("mnc").unwrap().u64().unwrap().get(i).unwrap();
+        let cell = RadioCell::from_row(df, i);
         //find the vincenty distance
-        let distance = geo_utils::vincenty_dist_between_coordinates((lat, lon), (point.shape_pt_lat, point.shape_pt_lon));
+        let distance = geo_utils::vincenty_dist_between_coordinates((cell.lat, cell.lon), (point.shape_pt_lat, point.shape_pt_lon));
         if distance < min_distance {
             min_distance = distance;
-            closest = Some(RadioCell {
-                lat,
-                lon,
-                id: id,
-                range: range,
-                mnc,
-            });
+            closest = Some(cell);
         }
     }
     println!("Finished {}", df.height());
@@ -1,12 +1,105 @@
-use rusqlite::{Connection, named_params, Result};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use rusqlite::{Connection, Result};
 use simulation_curator::gtfs;
 use simulation_curator::cell_data;
+use simulation_curator::connection_log::read_connection_log;
+use simulation_curator::gtfs::PartialBlock;
 use simulation_curator::nes_simulation;
-use simulation_curator::nes_simulation::create_single_fog_layer_topology_from_cell_data;
-use clap::Parser;
+use simulation_curator::manifest::{NodeManifest, NodeRole};
+use simulation_curator::nes_simulation::{create_single_fog_layer_topology_from_cell_data, create_single_fog_layer_topology_from_sites, read_locations_from_csv};
+use simulation_curator::sites::{group_cells_into_sites, SiteAggregation};
+use simulation_curator::backhaul::{add_backhaul_links, top_level_nodes, Backhaul, DistanceLatencyModel};
+use simulation_curator::link_model::{radio_of_nodes, LinkModel};
+use simulation_curator::handover::{HandoverMode, HandoverTiming, InterruptionGap};
+use simulation_curator::attachment_intervals::{write_intervals_to_csv, write_intervals_to_parquet};
+use simulation_curator::event_rate::{search_sampling_fraction, search_speedup, RateDistribution, RateTarget};
+use simulation_curator::time_mapping::TimeMapping;
+use simulation_curator::shape_synthesis::{read_polylines, ShapeSynthesis};
+use simulation_curator::block_synthesis::{synthesize_blocks, BlockSynthesis};
+use simulation_curator::frequencies::{expand_frequencies, read_frequencies, Frequency};
+use simulation_curator::bulk_loader::{load_schedules, ScheduleSelection};
+use simulation_curator::perturbation::{DelayDistribution, SchedulePerturbation};
+use simulation_curator::gtfs_realtime::{RealtimeArchive, ServiceDay};
+use simulation_curator::movement::VehicleKind;
+use simulation_curator::scenarios::{parse_window, periodic_windows, scenario_windows, write_summary_to_csv, ScenarioSummary, ScenarioWindow};
+use simulation_curator::tower_selection::{select_medoid_locations, select_towers, TowerSelection};
+use clap::{Parser, ValueEnum};
+use rayon::prelude::*;
+use polars::prelude::DataFrame;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum SiteAggregationArg {
+    /// Create one fog node per radio cell
+    None,
+    /// Merge cells located within the site tolerance of each other
+    Location,
+    /// Merge LTE cells sharing the same eNodeB id
+    Enodeb,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum TowerSelectionArg {
+    /// Cluster the towers with k-means and keep the tower closest to each centroid
+    KMeans,
+    /// Cluster the towers with k-medoids and keep the medoids
+    KMedoids,
+    /// Greedily keep the towers covering most of the trajectory
+    GreedyCoverage,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum BackhaulArg {
+    /// Do not create links between fog nodes
+    None,
+    /// Minimum spanning tree of the node locations
+    Mst,
+    /// Connect every node to its closest neighbours
+    Knn,
+    /// Delaunay triangulation of the node locations
+    Delaunay,
+    /// Attach every node to its closest aggregation point
+    Star,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum HandoverArg {
+    /// Remove the old and add the new link in the same batch
+    Instant,
+    /// Add the new link first and remove the old link after the overlap
+    MakeBeforeBreak,
+    /// Remove the old link first and add the new link after the interruption gap
+    BreakBeforeMake,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum CompressionProfileArg {
+    /// Compress the whole schedule by the same factor
+    Uniform,
+    /// Compress periods with few reconnects more than periods with many reconnects
+    Activity,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum BlockSynthesisArg {
+    /// Drop trips without a block id
+    None,
+    /// Create one vehicle per trip
+    PerTrip,
+    /// Chain trips of a route whose end and start stop and time are compatible into one vehicle
+    Chain,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum RealtimeModeArg {
+    /// Vehicles follow the recorded vehicle positions instead of the schedule
+    Positions,
+    /// Vehicles follow the schedule with the recorded arrival and departure times of the trip updates
+    TripUpdates,
+}
 
 /// Program to generate topology change events
-#[derive(Parser, Debug)]
+#[derive(Parser, Clone, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
     /// Path to the gtfs database
@@ -25,6 +118,26 @@ struct Args {
     #[arg(long, default_value = "1")]
     day_of_the_week: String,
 
+    /// Comma separated time windows of the form HH:MM:SS-HH:MM:SS. Every window is created as a scenario of its own in the scenario directory.
+    #[arg(long, num_args(0..), value_delimiter = ',', conflicts_with = "sample_period_in_seconds")]
+    windows: Vec<String>,
+
+    /// If set, a scenario is created for the windows starting every period between the start time and the end time
+    #[arg(long, default_value = None)]
+    sample_period_in_seconds: Option<u64>,
+
+    /// The length of the periodic windows. Defaults to the sample period.
+    #[arg(long, default_value = None, requires = "sample_period_in_seconds")]
+    sample_length_in_seconds: Option<u64>,
+
+    /// Comma separated days of the week on which every window is created as a scenario. Defaults to the day of the week.
+    #[arg(long, num_args(0..), value_delimiter = ',')]
+    sample_days: Vec<String>,
+
+    /// The directory in which the scenarios are created, each in a directory of its own, together with summary.csv
+    #[arg(long, default_value = "scenarios")]
+    scenario_dir: String,
+
     /// Comma separated short names of the lines for which the schedule needs to be extracted.
     #[arg(long, default_value = "S41,S42", num_args(0..), value_delimiter = ',')]
     line_names: Vec<String>,
@@ -33,8 +146,8 @@ struct Args {
     #[arg(long, default_value_t = 20)]
     batch_interval_size_in_seconds: u64,
 
-    /// The frequency at which the batch of topology changes needs to be produced.
-    #[arg(long, default_value_t = 500)]
+    /// The frequency at which the batch of topology changes needs to be produced. Must be at least 1.
+    #[arg(long, default_value_t = 500, value_parser = clap::value_parser!(u64).range(1..))]
     batch_frequency_in_milliseconds: u64,
 
     /// Path to the file where fixed_topology.json will be produced
@@ -53,6 +166,86 @@ struct Args {
     #[arg(short, long, default_value = "OpenCelliDGermanyData.csv")]
     open_cell_id_data_loc: String,
 
+    /// If set, the mobile nodes are created from a csv file of serving cell measurements with the header
+    /// "timestamp,vehicle,radio,mcc,mnc,area_code,cell_id,lat,lon" instead of the gtfs schedule
+    #[arg(long, default_value = None)]
+    connection_log_path: Option<String>,
+
+    /// Path to a csv file with the header "radio,mcc,mnc,area_code,cell_id,lat,lon,range" locating the cells of the connection log. If given,
+    /// the OpenCelliD data is not read for the connection log and measurements of cells missing in the list are skipped
+    #[arg(long, default_value = None, requires = "connection_log_path")]
+    cell_list_path: Option<String>,
+
+    /// If set, every trip starts with a log-normally distributed delay with this median
+    #[arg(long, default_value = None)]
+    trip_delay_median_in_seconds: Option<f64>,
+
+    /// Standard deviation of the logarithm of the trip delay
+    #[arg(long, default_value_t = 1.0)]
+    trip_delay_sigma: f64,
+
+    /// If set, every segment between two stops adds a log-normally distributed delay with this median
+    #[arg(long, default_value = None)]
+    segment_delay_median_in_seconds: Option<f64>,
+
+    /// Standard deviation of the logarithm of the segment delay
+    #[arg(long, default_value_t = 1.0)]
+    segment_delay_sigma: f64,
+
+    /// If set, the dwell time at every stop changes by a normally distributed amount with this standard deviation
+    #[arg(long, default_value = None)]
+    dwell_noise_in_seconds: Option<f64>,
+
+    /// A delayed vehicle starts its next trip at the earliest when it finished the previous one
+    #[arg(long, default_value_t = false)]
+    propagate_delays: bool,
+
+    /// Path to a directory of recorded GTFS-Realtime feeds, one serialized FeedMessage per .pb file
+    #[arg(long, default_value = None, requires = "realtime_service_date")]
+    realtime_dir: Option<String>,
+
+    /// Which part of the recorded GTFS-Realtime feeds is replayed
+    #[arg(long, value_enum, default_value_t = RealtimeModeArg::Positions, requires = "realtime_dir")]
+    realtime_mode: RealtimeModeArg,
+
+    /// The service date (YYYYMMDD) whose vehicles and trips are replayed from the realtime feeds
+    #[arg(long, default_value = None, requires = "realtime_dir")]
+    realtime_service_date: Option<String>,
+
+    /// IANA time zone of the schedule, e.g. Europe/Berlin, used to convert the timestamps of the realtime feeds. Defaults to the agency_timezone of the gtfs database.
+    #[arg(long, default_value = None, requires = "realtime_dir")]
+    realtime_timezone: Option<String>,
+
+    /// Build the trajectories of the blocks in parallel
+    #[arg(long, default_value_t = false)]
+    parallel_loading: bool,
+
+    /// How trips without a block id are assigned to vehicles. By default they are dropped. The runs of frequency based trips
+    /// are chained in the same way and otherwise served by one vehicle each.
+    #[arg(long, value_enum, default_value_t = BlockSynthesisArg::None)]
+    block_synthesis: BlockSynthesisArg,
+
+    /// The longest time a vehicle waits at the end of a trip for its next trip when chaining trips without a block id
+    #[arg(long, default_value_t = 900)]
+    max_layover_in_seconds: u64,
+
+    /// The largest distance in meters between the last stop of a trip and the first stop of the next trip of the same vehicle when chaining trips
+    #[arg(long, default_value_t = 200.0)]
+    max_layover_distance_in_m: f64,
+
+    /// Build a trajectory from the stops of trips that have no shape instead of dropping them
+    #[arg(long, default_value_t = false)]
+    synthesize_missing_shapes: bool,
+
+    /// Maximum distance in meters between two points of a synthesized trajectory. Points are inserted along the great circle between consecutive stops.
+    #[arg(long, default_value = None, requires = "synthesize_missing_shapes")]
+    shape_densify_spacing_in_m: Option<f64>,
+
+    /// Path to a GeoJSON file with rail or road lines, e.g. exported from OpenStreetMap, which synthesized trajectories follow.
+    /// Lines with a "route" property are only used for the route with this short name.
+    #[arg(long, default_value = None, requires = "synthesize_missing_shapes")]
+    shape_polyline_path: Option<String>,
+
     /// The minimum number of measurements required for a cellular base station to be included in the experiment.
     #[arg(short, long, default_value_t = 10)]
     min_samples: u64,
@@ -61,6 +254,150 @@ struct Args {
     #[arg(short, long, default_value = "LTE")]
     radio: String,
 
+    /// How radio cells are grouped into physical sites. Each site becomes a single fog node with the summed slots of its cells.
+    #[arg(long, value_enum, default_value_t = SiteAggregationArg::None)]
+    site_aggregation: SiteAggregationArg,
+
+    /// The maximum distance in meters between cells of the same site when aggregating by location.
+    #[arg(long, default_value_t = 30.0)]
+    site_tolerance_in_meters: f64,
+
+    /// The exact number of radio cells to keep. If set, the cells found along the trajectories are reduced to this number before the topology is created.
+    #[arg(long, default_value = None)]
+    target_fog_nodes: Option<usize>,
+
+    /// The strategy used to select the radio cells when a target number of fog nodes is set.
+    #[arg(long, value_enum, default_value_t = TowerSelectionArg::KMedoids)]
+    tower_selection: TowerSelectionArg,
+
+    /// Number of edge servers placed at a subset of the tower locations. Each tower becomes a child of its closest edge server.
+    #[arg(long, default_value = None, conflicts_with = "edge_server_locations_path")]
+    edge_servers: Option<usize>,
+
+    /// Path to a csv file with the columns lat,lon containing the locations of the edge servers.
+    #[arg(long, default_value = None)]
+    edge_server_locations_path: Option<String>,
+
+    /// The number of slots of a tower that is attached to an edge server.
+    #[arg(long, default_value_t = 1)]
+    edge_tower_slots: u16,
+
+    /// The graph used to create backhaul links between the top level fog nodes (or edge servers).
+    #[arg(long, value_enum, default_value_t = BackhaulArg::None)]
+    backhaul: BackhaulArg,
+
+    /// The number of neighbours each node is connected to in a knn backhaul.
+    #[arg(long, default_value_t = 3)]
+    backhaul_neighbours: usize,
+
+    /// Path to a csv file with the columns lat,lon containing the aggregation points of a star backhaul.
+    #[arg(long, default_value = None, required_if_eq("backhaul", "star"))]
+    aggregation_points_path: Option<String>,
+
+    /// If set, the latency of every backhaul link is estimated from its length with this latency per kilometer.
+    #[arg(long, default_value = None)]
+    backhaul_latency_per_km_in_ms: Option<f64>,
+
+    /// The constant part of the estimated latency of a backhaul link.
+    #[arg(long, default_value_t = 0.0)]
+    backhaul_base_latency_in_ms: f64,
+
+    /// Add latency, bandwidth and packet loss to every fixed link and to every link created by an add event.
+    #[arg(long, default_value_t = false)]
+    link_attributes: bool,
+
+    /// Path to a json file overriding the default link model. Implies link_attributes.
+    #[arg(long, default_value = None)]
+    link_model_path: Option<String>,
+
+    /// The id of the coordinator. Fog nodes are numbered starting after this id.
+    #[arg(long, default_value_t = 1)]
+    root_id: u64,
+
+    /// Write the coordinator as root node into fixed_topology.json instead of relying on the id convention.
+    #[arg(long, default_value_t = false)]
+    include_root: bool,
+
+    /// Comma separated latitude and longitude of the root node. Defaults to the centroid of all other nodes.
+    #[arg(long, value_parser = nes_simulation::parse_location, requires = "include_root")]
+    root_location: Option<(f64, f64)>,
+
+    /// The number of slots of the root node.
+    #[arg(long, default_value_t = u16::MAX)]
+    root_slots: u16,
+
+    /// Connect all nodes without a parent to the root node.
+    #[arg(long, default_value_t = false, requires = "include_root")]
+    root_to_fog_edges: bool,
+
+    /// If set, the schedule is compressed into this emulation runtime instead of using the batch interval size. Batches are emitted every batch frequency.
+    #[arg(long, default_value = None, conflicts_with = "speedup")]
+    target_runtime_in_seconds: Option<f64>,
+
+    /// If set, the schedule is compressed by this factor instead of using the batch interval size. Batches are emitted every batch frequency.
+    #[arg(long, default_value = None)]
+    speedup: Option<f64>,
+
+    /// How the schedule is compressed when a target runtime or speedup is set
+    #[arg(long, value_enum, default_value_t = CompressionProfileArg::Uniform)]
+    compression_profile: CompressionProfileArg,
+
+    /// Share of the emulation runtime distributed by the number of reconnects in the activity profile, the rest is distributed by real time
+    #[arg(long, default_value_t = 0.5)]
+    activity_weight: f64,
+
+    /// Length of the periods in seconds whose reconnects are counted by the activity profile
+    #[arg(long, default_value_t = 60)]
+    activity_segment_in_seconds: u64,
+
+    /// If set, the speedup of the schedule is chosen so that the mean number of reconnects per second is close to this value
+    #[arg(long, default_value = None, conflicts_with = "target_peak_rate")]
+    target_mean_rate: Option<f64>,
+
+    /// If set, the speedup of the schedule is chosen so that the maximum number of reconnects per second is close to this value
+    #[arg(long, default_value = None)]
+    target_peak_rate: Option<f64>,
+
+    /// Reach the target rate by sampling a fraction of the vehicles instead of changing the speedup. Requires a target runtime or speedup.
+    #[arg(long, default_value_t = false)]
+    rate_search_sampling: bool,
+
+    /// Path to the file where the mapping from real to emulated time will be produced
+    #[arg(long, default_value = "time_mapping.json")]
+    time_mapping_path: String,
+
+    /// Order and timing of the remove and add event of a handover
+    #[arg(long, value_enum, default_value_t = HandoverArg::Instant)]
+    handover: HandoverArg,
+
+    /// Time in milliseconds during which a mobile node is connected to the old and the new parent in make-before-break handovers
+    #[arg(long, default_value_t = 0)]
+    handover_overlap_in_ms: u64,
+
+    /// Time in milliseconds during which a mobile node is disconnected in break-before-make handovers
+    #[arg(long, default_value_t = 0)]
+    handover_gap_in_ms: u64,
+
+    /// If set, the interruption gap of each break-before-make handover is drawn uniformly between the handover gap and this value
+    #[arg(long, default_value = None)]
+    handover_max_gap_in_ms: Option<u64>,
+
+    /// If set, updates with more events are split and the remaining events are moved to later updates
+    #[arg(long, default_value = None)]
+    max_events_per_update: Option<usize>,
+
+    /// If set, events exceeding this number of events per second are moved to the next second
+    #[arg(long, default_value = None)]
+    max_events_per_second: Option<usize>,
+
+    /// The time in milliseconds between the parts of a split update
+    #[arg(long, default_value_t = 100)]
+    split_spacing_in_ms: u64,
+
+    /// Seed of the random number generators used for random handover gaps and schedule perturbations
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
     /// The amoount of physical sources corresponding to a single logical source
     #[arg(long, default_value = None)]
     source_group_size: Option<u16>,
@@ -72,90 +409,520 @@ struct Args {
     /// Path to the file where source_groups_geo.json will be produced
     #[arg(long, default_value = "source_groups_geo.json")]
     source_group_geo_path: String,
+
+    /// Path to the file where nodes_manifest.json will be produced
+    #[arg(long, default_value = "nodes_manifest.json")]
+    manifest_path: String,
+
+    /// Path to the file where nodes_manifest.csv will be produced
+    #[arg(long, default_value = "nodes_manifest.csv")]
+    manifest_csv_path: String,
+
+    /// Path to the file where attachment_intervals.csv will be produced
+    #[arg(long, default_value = "attachment_intervals.csv")]
+    attachment_intervals_path: String,
+
+    /// Path to the file where attachment_intervals.parquet will be produced
+    #[arg(long, default_value = "attachment_intervals.parquet")]
+    attachment_intervals_parquet_path: String,
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+impl Args {
+    //the same arguments with every output file placed in the directory
+    fn in_directory(&self, directory: &Path) -> Args {
+        let mut args = self.clone();
+        for path in [&mut args.topology_path, &mut args.topology_updates_path, &mut args.geo_json_path, &mut args.time_mapping_path, &mut args.source_group_path,
+                     &mut args.source_group_geo_path, &mut args.manifest_path, &mut args.manifest_csv_path, &mut args.attachment_intervals_path, &mut args.attachment_intervals_parquet_path] {
+            let file_name = Path::new(path.as_str()).file_name().unwrap().to_owned();
+            *path = directory.join(file_name).to_string_lossy().to_string();
+        }
+        args
+    }
+}
 
-    //db
-    let db = Connection::open(args.db_path)?;
+//inputs that are read once and shared by all scenarios of a run
+struct SharedInputs {
+    db: Option<Connection>,
+    //the selected trips of every day of the week
+    schedules: HashMap<String, ScheduleSelection>,
+    frequencies: HashMap<String, Vec<Frequency>>,
+    shape_synthesis: Option<ShapeSynthesis>,
+    realtime: Option<RealtimeArchive>,
+    cell_data: Option<DataFrame>,
+}
+
+impl SharedInputs {
+    fn read(args: &Args) -> Result<Self> {
+        let db = match args.connection_log_path {
+            Some(_) => None,
+            None => Some(Connection::open(&args.db_path)?),
+        };
+        //template trips of frequencies.txt are only served through their expanded runs
+        let frequencies = db.as_ref().map(|db| read_frequencies(db).unwrap()).unwrap_or_default();
+        let shape_synthesis = args.synthesize_missing_shapes.then(|| ShapeSynthesis {
+            densify_spacing: args.shape_densify_spacing_in_m,
+            polylines: args.shape_polyline_path.as_deref().map(|path| read_polylines(path).unwrap()).unwrap_or_default(),
+        });
+        let realtime = args.realtime_dir.as_deref().map(|path| {
+            let timezone = match &args.realtime_timezone {
+                Some(timezone) => timezone.clone(),
+                None => db.as_ref().unwrap().query_row("SELECT agency_timezone FROM agency LIMIT 1", [], |row| row.get(0))
+                    .expect("The gtfs database has no agency time zone, set it with --realtime-timezone"),
+            };
+            let service_day = ServiceDay::new(args.realtime_service_date.as_ref().unwrap(), &timezone).unwrap();
+            RealtimeArchive::read_directory(path, &service_day).unwrap()
+        });
+        Ok(SharedInputs { db, schedules: HashMap::new(), frequencies, shape_synthesis, realtime, cell_data: None })
+    }
+
+    //the schedules of a day are loaded when they are needed first
+    fn schedules(&mut self, args: &Args, day_of_the_week: &str) -> ScheduleSelection {
+        let db = self.db.as_ref().unwrap();
+        self.schedules.entry(day_of_the_week.to_string())
+            .or_insert_with(|| load_schedules(db, &args.line_names, day_of_the_week).unwrap())
+            .clone()
+    }
+
+    fn cell_data(&mut self, args: &Args) -> &DataFrame {
+        self.cell_data.get_or_insert_with(|| {
+            let network_id = vec![2, 4, 9]; // For vodafone
+            let beginning_of_2024 = 1704067200;
+            let filter = cell_data::CellFilter { radio: args.radio.clone(), mcc: 262, mncs: network_id, start_time: 0, updated: beginning_of_2024, sample_count: args.min_samples };
+            cell_data::read_filtered_cell_data(&(args.open_cell_id_data_loc), &filter)
+        })
+    }
+}
+
+fn create_time_mapping(profile: CompressionProfileArg, activity_segment: std::time::Duration, activity_weight: f64, start_time: std::time::Duration, real_duration: std::time::Duration, target_runtime: std::time::Duration, reconnects: &nes_simulation::SimulatedReconnects) -> TimeMapping {
+    match profile {
+        CompressionProfileArg::Uniform => TimeMapping::uniform(start_time, real_duration, target_runtime),
+        CompressionProfileArg::Activity => {
+            let reconnect_offsets: Vec<std::time::Duration> = reconnects.topology_updates.iter()
+                .flat_map(|u| u.events.iter().filter(|e| e.action == nes_simulation::ISQPEventAction::add).map(|_| u.timestamp))
+                .collect();
+            TimeMapping::activity_weighted(start_time, real_duration, target_runtime, &reconnect_offsets, activity_segment, activity_weight)
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
 
     //time window
     let start_time = gtfs::parse_duration(&(args.start_time)).unwrap();
     let end_time = gtfs::parse_duration(&(args.end_time)).unwrap();
+    let mut inputs = SharedInputs::read(&args)?;
 
-    // Compute concatenated line names
-    let line_names = args.line_names.iter().map(|line_name| format!("'{}'", line_name))
-        .collect::<Vec<_>>()
-        .join(",");
-
-    let sql_query = format!("{}{}{}", "SELECT DISTINCT block_id, routes.route_short_name \
-                                                  FROM routes, trips, calendar_dates \
-                                                  WHERE routes.route_id=trips.route_id \
-                                                  AND trips.service_id=calendar_dates.service_id \
-                                                  AND routes.route_short_name in (", line_names, ") \
-                                                  AND trips.block_id NOTNULL
-                                                  AND calendar_dates.date=( \
-                                                        SELECT min(calendar_dates.date) \
-                                                        FROM calendar_dates \
-                                                        WHERE strftime('%w',calendar_dates.date) =:day_of_the_week)");
-
-    println!("SQL {}", sql_query);
-    // get routes and trips for a specific calender date
-    let mut stmt = db.prepare(sql_query.as_str())?;
-
-    let block_ids = stmt.query_map(named_params! {":day_of_the_week": args.day_of_the_week},
-                                   |row| { Ok((row.get::<usize, String>(0), row.get::<usize, String>(1))) })?;
-
-    let mut partial_blocks = Vec::new();
-    for block_id in block_ids {
-        if let Ok((block_id, route_id)) = block_id {
-            if let Some(trip) = gtfs::read_stops_for_block(block_id?, route_id?, &db, start_time, end_time).unwrap() {
-                partial_blocks.push(trip);
+    //without several windows or days a single scenario is written to the configured paths
+    if args.windows.is_empty() && args.sample_period_in_seconds.is_none() && args.sample_days.is_empty() {
+        let window = ScenarioWindow { day_of_the_week: args.day_of_the_week.clone(), start_time, end_time };
+        run_scenario(&args, &window, &mut inputs)?;
+        return Ok(());
+    }
+    let windows = if let Some(period) = args.sample_period_in_seconds {
+        let length = args.sample_length_in_seconds.unwrap_or(period);
+        periodic_windows(start_time, end_time, std::time::Duration::from_secs(period), std::time::Duration::from_secs(length))
+    } else if !args.windows.is_empty() {
+        args.windows.iter().map(|window| parse_window(window).unwrap()).collect()
+    } else {
+        vec![(start_time, end_time)]
+    };
+    let days = if args.sample_days.is_empty() { vec![args.day_of_the_week.clone()] } else { args.sample_days.clone() };
+    let mut summaries = vec![];
+    for window in scenario_windows(&days, &windows) {
+        println!("Creating scenario {}", window.name());
+        let directory = Path::new(&args.scenario_dir).join(window.name());
+        std::fs::create_dir_all(&directory).unwrap();
+        summaries.push(run_scenario(&args.in_directory(&directory), &window, &mut inputs)?);
+    }
+    let summary_path = Path::new(&args.scenario_dir).join("summary.csv");
+    write_summary_to_csv(&summaries, &summary_path.to_string_lossy()).unwrap();
+    println!("Created {} scenarios, the summary is written to {}", summaries.len(), summary_path.display());
+    Ok(())
+}
+
+//create the topology, the topology updates and all other output files of one time window. Errors in the inputs of the window
+//are returned instead of aborting the other scenarios of the run
+fn run_scenario(args: &Args, window: &ScenarioWindow, inputs: &mut SharedInputs) -> Result<ScenarioSummary, Box<dyn std::error::Error>> {
+    let (start_time, end_time) = (window.start_time, window.end_time);
+
+    //the mobile nodes either follow a schedule or a measured connection log
+    let (partial_blocks, mut cells) = if let Some(log_path) = &args.connection_log_path {
+        let cells = read_connection_log(log_path, args.cell_list_path.is_none().then_some(args.open_cell_id_data_loc.as_str()), args.cell_list_path.as_deref(), start_time, end_time)?;
+        let partial_blocks: Vec<PartialBlock> = cells.trips.values().flatten().map(|t| t.trip.clone()).collect();
+        (partial_blocks, cells)
+    } else {
+        let block_synthesis = match args.block_synthesis {
+            BlockSynthesisArg::None => None,
+            BlockSynthesisArg::PerTrip => Some(BlockSynthesis::PerTrip),
+            BlockSynthesisArg::Chain => Some(BlockSynthesis::Chain {
+                max_layover: std::time::Duration::from_secs(args.max_layover_in_seconds),
+                max_stop_distance: args.max_layover_distance_in_m,
+            }),
+        };
+        let delay = |median: Option<f64>, sigma: f64| median.map(|median| DelayDistribution { median: std::time::Duration::from_secs_f64(median), sigma });
+        let trip_delay = delay(args.trip_delay_median_in_seconds, args.trip_delay_sigma);
+        let segment_delay = delay(args.segment_delay_median_in_seconds, args.segment_delay_sigma);
+        let dwell_noise = args.dwell_noise_in_seconds.map(std::time::Duration::from_secs_f64);
+        let mut schedule_perturbation = (trip_delay.is_some() || segment_delay.is_some() || dwell_noise.is_some())
+            .then(|| SchedulePerturbation::new(trip_delay, segment_delay, dwell_noise, args.propagate_delays, args.seed));
+
+        let mut selection = inputs.schedules(args, &window.day_of_the_week);
+        let (frequencies, shape_synthesis, realtime) = (&inputs.frequencies, inputs.shape_synthesis.as_ref(), &inputs.realtime);
+        let trip_routes: HashMap<String, String> = selection.trips.iter().map(|t| (t.trip_id.clone(), t.route_id.clone())).collect();
+        let vehicle_kinds: HashMap<String, VehicleKind> = selection.trips.iter().map(|t| (t.route_id.clone(), VehicleKind::from_route_type(t.route_type))).collect();
+
+        //all trips of a block are run by the same vehicle, the first trip of a block determines its route
+        let mut blocks: BTreeMap<String, (String, Vec<gtfs::TripSchedule>)> = BTreeMap::new();
+        let mut trips_per_route: BTreeMap<String, Vec<gtfs::TripSchedule>> = BTreeMap::new();
+        for trip in selection.trips.clone() {
+            let schedule = selection.take_schedule(&trip.trip_id);
+            if let Some(trip_frequencies) = frequencies.get(&trip.trip_id) {
+                //every departure of a template trip becomes a trip of its own
+                let runs = expand_frequencies(&schedule, trip_frequencies, start_time, end_time)?;
+                println!("Expanded template trip {} into {} runs", trip.trip_id, runs.len());
+                trips_per_route.entry(trip.route_id).or_default().extend(runs);
+            } else if let Some(block_id) = trip.block_id {
+                blocks.entry(block_id).or_insert_with(|| (trip.route_id, vec![])).1.push(schedule);
+            } else if block_synthesis.is_some() {
+                //trips without a block id are assigned to vehicles by the block synthesis
+                trips_per_route.entry(trip.route_id).or_default().push(schedule);
+            }
+        }
+        for (route_id, trips) in trips_per_route {
+            for (block_id, block_trips) in synthesize_blocks(&route_id, trips, block_synthesis.unwrap_or(BlockSynthesis::PerTrip)) {
+                blocks.insert(block_id, (route_id.clone(), block_trips));
             }
         }
-    }
 
-    println!("Simulation contains {} mobile nodes", partial_blocks.len());
+        if let (Some(realtime), RealtimeModeArg::TripUpdates) = (&realtime, args.realtime_mode) {
+            let mut updated_trips = 0;
+            for trip in blocks.values_mut().flat_map(|(_, trips)| trips.iter_mut()) {
+                if realtime.apply_trip_updates(trip)? {
+                    updated_trips += 1;
+                }
+            }
+            println!("Applied realtime updates to {} trips", updated_trips);
+        }
+        if let Some(perturbation) = schedule_perturbation.as_mut() {
+            for (_, trips) in blocks.values_mut() {
+                perturbation.perturb_block(trips)?;
+            }
+        }
 
-    // Find the cell towers used for connection
-    let network_id = vec![2, 4, 9]; // For vodafone
-    let beginning_of_2024 = 1704067200;
-    let cells = cell_data::get_closest_cells_from_csv(&(args.open_cell_id_data_loc), &(args.radio), 262, &network_id, 0, beginning_of_2024, args.min_samples, &partial_blocks);
+        //the error is passed on as a message because it has to be sent between the threads of the parallel loading
+        let build_block = |(block_id, (route_id, trips)): (String, (String, Vec<gtfs::TripSchedule>))| {
+            let profile = vehicle_kinds.get(&route_id).copied().unwrap_or(VehicleKind::Bus).profile();
+            gtfs::build_partial_block(block_id.clone(), route_id, trips, start_time, end_time, shape_synthesis, profile)
+                .map_err(|e| format!("Could not build block {}: {}", block_id, e))
+        };
+        let partial_blocks: Vec<PartialBlock> = if let (Some(realtime), RealtimeModeArg::Positions) = (&realtime, args.realtime_mode) {
+            //the observed positions replace the trajectories derived from the schedule
+            realtime.vehicle_blocks(&trip_routes, start_time, end_time)
+        } else if args.parallel_loading {
+            blocks.into_par_iter().map(build_block).collect::<Result<Vec<_>, String>>()?.into_iter().flatten().collect()
+        } else {
+            blocks.into_iter().map(build_block).collect::<Result<Vec<_>, String>>()?.into_iter().flatten().collect()
+        };
+
+        println!("Simulation contains {} mobile nodes", partial_blocks.len());
+
+        // Find the cell towers used for connection
+        let cells = cell_data::get_closest_cells(inputs.cell_data(args), &partial_blocks);
+        (partial_blocks, cells)
+    };
     println!("Simulation contains {} radio cells", cells.radio_cells.len());
+    if partial_blocks.is_empty() || cells.radio_cells.is_empty() {
+        //e.g. a window without service, the other scenarios of the run are still created
+        println!("No mobile nodes or radio cells in the time window, no output files are written");
+        return Ok(ScenarioSummary::new(window));
+    }
+    if let Some(target_fog_nodes) = args.target_fog_nodes {
+        let selection = match args.tower_selection {
+            TowerSelectionArg::KMeans => TowerSelection::KMeans,
+            TowerSelectionArg::KMedoids => TowerSelection::KMedoids,
+            TowerSelectionArg::GreedyCoverage => TowerSelection::GreedyCoverage,
+        };
+        select_towers(&mut cells, target_fog_nodes, selection);
+        println!("Reduced simulation to {} radio cells", cells.radio_cells.len());
+    }
     let gj = cells.to_geojson();
 
-    std::fs::write(args.geo_json_path, gj.to_string()).unwrap();
+    std::fs::write(&args.geo_json_path, gj.to_string())?;
 
     //set default resources to max value
     let default_resources = u16::MAX;
+    //unless the root is included, the coordinator is not part of the topology file and only known by its id
+    let coordinator_id = args.root_id;
     //create a topology and write it to json
-    let (topology, cell_id_to_node_id) = create_single_fog_layer_topology_from_cell_data(2, default_resources, &cells);
-    topology.write_to_file(&(args.topology_path)).unwrap();
+    let site_aggregation = match args.site_aggregation {
+        SiteAggregationArg::None => None,
+        SiteAggregationArg::Location => Some(SiteAggregation::Location { tolerance_in_meters: args.site_tolerance_in_meters }),
+        SiteAggregationArg::Enodeb => Some(SiteAggregation::ENodeB),
+    };
+    let (mut topology, cell_id_to_node_id) = match site_aggregation {
+        Some(aggregation) => {
+            let sites = group_cells_into_sites(cells.radio_cells.values(), aggregation);
+            create_single_fog_layer_topology_from_sites(coordinator_id + 1, default_resources, &sites)
+        }
+        None => create_single_fog_layer_topology_from_cell_data(coordinator_id + 1, default_resources, &cells),
+    };
+
+    //optionally place edge servers above the towers
+    let edge_server_locations = if let Some(path) = &args.edge_server_locations_path {
+        Some(read_locations_from_csv(path)?)
+    } else if let Some(edge_servers) = args.edge_servers {
+        let mut tower_ids: Vec<u64> = topology.nodes.keys().copied().collect();
+        tower_ids.sort();
+        let tower_locations: Vec<(f64, f64)> = tower_ids.iter().map(|id| (topology.nodes[id][1], topology.nodes[id][0])).collect();
+        Some(select_medoid_locations(&tower_locations, edge_servers).iter().map(|i| tower_locations[*i]).collect())
+    } else {
+        None
+    };
+    let server_of_node = edge_server_locations.map(|locations| {
+        println!("Placing {} edge servers above the towers", locations.len());
+        topology.add_edge_server_tier(&locations, default_resources, args.edge_tower_slots)
+    });
+
+    //optionally connect the top level nodes with backhaul links
+    let backhaul = match args.backhaul {
+        BackhaulArg::None => None,
+        BackhaulArg::Mst => Some(Backhaul::MinimumSpanningTree),
+        BackhaulArg::Knn => Some(Backhaul::NearestNeighbours { k: args.backhaul_neighbours }),
+        BackhaulArg::Delaunay => Some(Backhaul::Delaunay),
+        BackhaulArg::Star => Some(Backhaul::Star { aggregation_points: read_locations_from_csv(args.aggregation_points_path.as_ref().unwrap())? }),
+    };
+    let aggregation_nodes = backhaul.map(|backhaul| {
+        let latency_model = args.backhaul_latency_per_km_in_ms.map(|latency_per_km_in_ms| DistanceLatencyModel {
+            base_latency_in_ms: args.backhaul_base_latency_in_ms,
+            latency_per_km_in_ms,
+        });
+        let nodes = top_level_nodes(&topology);
+        add_backhaul_links(&mut topology, &nodes, &backhaul, default_resources, latency_model)
+    });
+    if args.include_root {
+        topology.add_root(coordinator_id, args.root_location, args.root_slots, args.root_to_fog_edges)?;
+    }
+
+    //optionally derive the properties of all links
+    let link_model = if let Some(path) = &args.link_model_path {
+        Some(LinkModel::read_from_file(path)?)
+    } else if args.link_attributes {
+        Some(LinkModel::default())
+    } else {
+        None
+    };
+    if let Some(link_model) = &link_model {
+        let edge_servers: HashSet<u64> = server_of_node.iter().flat_map(|s| s.values().copied()).collect();
+        link_model.annotate_topology(&mut topology, &edge_servers);
+    }
+    topology.write_to_file(&(args.topology_path))?;
+    let radio_cells = cells.radio_cells.clone();
 
     let batch_interval = std::time::Duration::from_secs(args.batch_interval_size_in_seconds);
     let batch_gap = std::time::Duration::from_millis(args.batch_frequency_in_milliseconds);
-    let (simulated_reconnects, trip_to_node, source_groups) = nes_simulation::SimulatedReconnects::from_topology_and_cell_data(topology, cells, cell_id_to_node_id, start_time, batch_interval.into(), batch_gap.into(), args.source_group_size);
+    let real_duration = end_time - start_time;
+    let group_size = args.source_group_size;
+    let activity_segment = std::time::Duration::from_secs(args.activity_segment_in_seconds);
+    let create_reconnects = |cells: cell_data::MultiTripAndCellData, batch_interval: Option<std::time::Duration>, batch_gap: Option<std::time::Duration>| {
+        nes_simulation::SimulatedReconnects::from_topology_and_cell_data(topology.clone(), cells, cell_id_to_node_id.clone(), start_time, batch_interval, batch_gap, group_size)
+    };
+
+    //optionally search the speedup or the vehicle sample reaching the target rate of reconnects
+    let rate_target = match (args.target_mean_rate, args.target_peak_rate) {
+        (Some(rate), _) => Some(RateTarget::Mean(rate)),
+        (None, Some(rate)) => Some(RateTarget::Peak(rate)),
+        (None, None) => None,
+    };
+    let mut target_runtime = match (args.target_runtime_in_seconds, args.speedup) {
+        (Some(runtime), _) => Some(std::time::Duration::from_secs_f64(runtime)),
+        (None, Some(speedup)) => Some(real_duration.div_f64(speedup)),
+        (None, None) => None,
+    };
+    if let Some(rate_target) = rate_target {
+        let measure = |reconnects: &nes_simulation::SimulatedReconnects, target_runtime: std::time::Duration| {
+            let time_mapping = create_time_mapping(args.compression_profile, activity_segment, args.activity_weight, start_time, real_duration, target_runtime, reconnects);
+            let mut reconnects = reconnects.clone();
+            reconnects.compress_time(&time_mapping, batch_gap);
+            RateDistribution::from_updates(&reconnects.topology_updates, target_runtime)
+        };
+        if args.rate_search_sampling {
+            let target_runtime = target_runtime.expect("Sampling the vehicles to reach a target rate requires a target runtime or speedup");
+            let (fraction, _) = search_sampling_fraction(rate_target, |fraction| {
+                let (reconnects, _, _) = create_reconnects(cells.sample_vehicles(fraction), None, None);
+                measure(&reconnects, target_runtime)
+            });
+            cells = cells.sample_vehicles(fraction);
+        } else {
+            let (reconnects, _, _) = create_reconnects(cells.clone(), None, None);
+            let (speedup, _) = search_speedup(rate_target, |speedup| measure(&reconnects, real_duration.div_f64(speedup)));
+            target_runtime = Some(real_duration.div_f64(speedup));
+            println!("Each batch emitted every {}ms represents {:.1}s of the schedule", batch_gap.as_millis(), batch_gap.as_secs_f64() * speedup);
+        }
+    }
+
+    //when compressing the time, the reconnects are created at their real time and batched afterwards
+    let (batch_interval_arg, batch_gap_arg) = if target_runtime.is_some() { (None, None) } else { (Some(batch_interval), Some(batch_gap)) };
+    let (mut simulated_reconnects, trip_to_node, source_groups) = create_reconnects(cells, batch_interval_arg, batch_gap_arg);
+    let time_mapping = if let Some(target_runtime) = target_runtime {
+        let time_mapping = create_time_mapping(args.compression_profile, activity_segment, args.activity_weight, start_time, real_duration, target_runtime, &simulated_reconnects);
+        simulated_reconnects.compress_time(&time_mapping, batch_gap);
+        time_mapping
+    } else {
+        TimeMapping::from_speedup(start_time, real_duration, batch_interval.as_secs_f64() / batch_gap.as_secs_f64())
+    };
+    time_mapping.print_summary();
+    time_mapping.write_to_file(&args.time_mapping_path)?;
+    let rate_distribution = RateDistribution::from_updates(&simulated_reconnects.topology_updates, time_mapping.points.last().unwrap().emulated_offset);
+    rate_distribution.print_summary();
+    if let Some(server_of_node) = &server_of_node {
+        simulated_reconnects.mark_intra_server_moves(server_of_node);
+    }
+    if let Some(link_model) = &link_model {
+        link_model.annotate_reconnects(&mut simulated_reconnects, &radio_of_nodes(&cell_id_to_node_id));
+    }
+    let handover_mode = match args.handover {
+        HandoverArg::Instant => HandoverMode::Instant,
+        HandoverArg::MakeBeforeBreak => HandoverMode::MakeBeforeBreak { overlap: std::time::Duration::from_millis(args.handover_overlap_in_ms) },
+        HandoverArg::BreakBeforeMake => {
+            let gap = match args.handover_max_gap_in_ms {
+                Some(max) => InterruptionGap::Uniform { min: std::time::Duration::from_millis(args.handover_gap_in_ms), max: std::time::Duration::from_millis(max) },
+                None => InterruptionGap::Fixed(std::time::Duration::from_millis(args.handover_gap_in_ms)),
+            };
+            HandoverMode::BreakBeforeMake { gap }
+        }
+    };
+    if handover_mode != HandoverMode::Instant {
+        simulated_reconnects.apply_handover_timing(&mut HandoverTiming::new(handover_mode, args.seed));
+    }
+    if args.max_events_per_update.is_some() || args.max_events_per_second.is_some() {
+        simulated_reconnects.limit_update_size(args.max_events_per_update, args.max_events_per_second, std::time::Duration::from_millis(args.split_spacing_in_ms));
+    }
     let events = simulated_reconnects.topology_updates.iter().map(|x| x.events.len()).sum::<usize>();
-    println!("Created {} batches containing {} events. Last batch will be emitted after {}s", simulated_reconnects.topology_updates.len(), events, simulated_reconnects.topology_updates.last().unwrap().timestamp.as_secs());
-    let json_string = serde_json::to_string_pretty(&simulated_reconnects).unwrap();
-    std::fs::write(args.topology_updates_path, json_string).unwrap();
+    //vehicles that never change their cell create no updates
+    let last_update = simulated_reconnects.topology_updates.last().map_or(0, |u| u.timestamp.as_secs());
+    println!("Created {} batches containing {} events. Last batch will be emitted after {}s", simulated_reconnects.topology_updates.len(), events, last_update);
+    let summary = ScenarioSummary {
+        mobile_nodes: trip_to_node.len(),
+        radio_cells: radio_cells.len(),
+        topology_updates: simulated_reconnects.topology_updates.len(),
+        events,
+        mean_reconnects_per_second: rate_distribution.mean(),
+        peak_reconnects_per_second: rate_distribution.peak(),
+        ..ScenarioSummary::new(window)
+    };
+    let json_string = serde_json::to_string_pretty(&simulated_reconnects)?;
+    std::fs::write(&args.topology_updates_path, json_string)?;
+
+    let intervals = simulated_reconnects.attachment_intervals(time_mapping.points.last().unwrap().emulated_offset, start_time, |t| time_mapping.unmap(t));
+    write_intervals_to_csv(&intervals, &args.attachment_intervals_path)?;
+    write_intervals_to_parquet(&intervals, &args.attachment_intervals_parquet_path)?;
+
+    let mut manifest = NodeManifest::new(coordinator_id, &topology, &radio_cells, &cell_id_to_node_id, &partial_blocks, &trip_to_node, simulated_reconnects.initial_parents());
+    if let Some(server_of_node) = &server_of_node {
+        let mut servers: Vec<u64> = server_of_node.values().copied().collect();
+        servers.sort();
+        servers.dedup();
+        manifest.add_nodes(&topology, &servers, NodeRole::EdgeServer);
+    }
+    if let Some(aggregation_nodes) = &aggregation_nodes {
+        manifest.add_nodes(&topology, aggregation_nodes, NodeRole::Aggregation);
+    }
+    manifest.write_to_file(&(args.manifest_path))?;
+    manifest.write_to_csv(&(args.manifest_csv_path))?;
 
     if let Some(source_groups) = source_groups {
-        let json_string = serde_json::to_string_pretty(&source_groups).unwrap();
-        std::fs::write(args.source_group_path, json_string).unwrap();
-
-        //iterate over the trips and print the first stop and the corresponding source group
-        for block in partial_blocks {
-            let node_id = trip_to_node.get(&block.block_id).unwrap();
-            let source_group = source_groups.get(node_id).unwrap();
-            if let Some(first_stop) = block.stops.first() {
-                println!("Block {}, Route {} interpolation started at stop: {} and is assigned source group: {}", block.block_id, block.route_id, first_stop.stop_name, source_group.first().unwrap());
-            } else {
-                panic!("Block: {}, source group: {} does not have any associated stops", block.block_id, source_group.first().unwrap());
+        let json_string = serde_json::to_string_pretty(&source_groups)?;
+        std::fs::write(&args.source_group_path, json_string)?;
+
+        print_source_groups(&partial_blocks, &trip_to_node, &source_groups);
+    }
+
+    Ok(summary)
+}
+
+/// Print the first stop and the source group of every block that is part of the simulation
+fn print_source_groups(partial_blocks: &[PartialBlock], trip_to_node: &HashMap<String, u64>, source_groups: &HashMap<u64, Vec<u64>>) {
+    for block in partial_blocks {
+        //vehicles that were not sampled have no node
+        let Some(node_id) = trip_to_node.get(&block.block_id) else {
+            continue;
+        };
+        let source_group = source_groups.get(node_id).unwrap();
+        if let Some(first_stop) = block.stops.first() {
+            println!("Block {}, Route {} interpolation started at stop: {} and is assigned source group: {}", block.block_id, block.route_id, first_stop.stop_name, source_group.first().unwrap());
+        } else {
+            //vehicles of a connection log or a realtime feed and vehicles between two stops for the whole window have no stops
+            println!("Block {}, Route {} has no stops and is assigned source group: {}", block.block_id, block.route_id, source_group.first().unwrap());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //write a connection log of vehicles that switch between the given cells every minute and parse the arguments of a run on it
+    fn connection_log_args(name: &str, vehicles: u64, cells: &[u64], arguments: &[&str]) -> Args {
+        let directory = std::env::temp_dir().join(name);
+        std::fs::create_dir_all(&directory).unwrap();
+        let (log_path, cell_list_path) = (directory.join("log.csv"), directory.join("cells.csv"));
+        let mut log = "timestamp,vehicle,radio,mcc,mnc,area_code,cell_id,lat,lon\n".to_string();
+        for vehicle in 1..=vehicles {
+            for minute in 0..10 {
+                log += &format!("{},v{},LTE,262,2,100,{},,\n", 28800 + minute * 60 + vehicle, vehicle, cells[minute as usize % cells.len()]);
             }
         }
+        std::fs::write(&log_path, log).unwrap();
+        std::fs::write(&cell_list_path, "radio,mcc,mnc,area_code,cell_id,lat,lon,range\nLTE,262,2,100,1000,52.50,13.40,\nLTE,262,2,100,1001,52.51,13.41,\n").unwrap();
+        let mut command_line = vec!["cmd_tool", "--connection-log-path", log_path.to_str().unwrap(), "--cell-list-path", cell_list_path.to_str().unwrap(), "--start-time", "08:00:00", "--end-time", "08:10:00"];
+        command_line.extend(arguments);
+        Args::parse_from(command_line).in_directory(&directory)
     }
 
-    Ok(())
+    fn run_window(args: &Args) -> ScenarioSummary {
+        let window = ScenarioWindow { day_of_the_week: args.day_of_the_week.clone(), start_time: std::time::Duration::from_secs(28800), end_time: std::time::Duration::from_secs(29400) };
+        run_scenario(args, &window, &mut SharedInputs::read(args).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_sampled_vehicles_with_source_groups() {
+        let args = connection_log_args("simulation_curator_sampled_vehicles", 4, &[1000, 1001], &["--speedup", "1", "--target-mean-rate", "0.035", "--rate-search-sampling", "--source-group-size", "2"]);
+        let summary = run_window(&args);
+        assert_eq!(summary.mobile_nodes, 2);
+
+        //only the sampled vehicles become mobile nodes and are assigned a source group
+        let manifest: NodeManifest = serde_json::from_str(&std::fs::read_to_string(&args.manifest_path).unwrap()).unwrap();
+        assert_eq!(manifest.nodes.iter().filter(|n| n.role == NodeRole::Mobile).count(), 2);
+        let source_groups: HashMap<u64, Vec<u64>> = serde_json::from_str(&std::fs::read_to_string(&args.source_group_path).unwrap()).unwrap();
+        assert_eq!(source_groups.len(), 2);
+    }
+
+    #[test]
+    fn test_scenario_without_reconnects() {
+        let args = connection_log_args("simulation_curator_without_reconnects", 2, &[1000], &[]);
+        let summary = run_window(&args);
+        assert_eq!((summary.mobile_nodes, summary.topology_updates, summary.events, summary.peak_reconnects_per_second), (2, 0, 0, 0));
+
+        //a window after the end of the log has no mobile nodes
+        let window = ScenarioWindow { day_of_the_week: args.day_of_the_week.clone(), start_time: std::time::Duration::from_secs(32400), end_time: std::time::Duration::from_secs(33000) };
+        let summary = run_scenario(&args, &window, &mut SharedInputs::read(&args).unwrap()).unwrap();
+        assert_eq!((summary.scenario.as_str(), summary.mobile_nodes, summary.events), ("day1_090000_091000", 0, 0));
+    }
+
+    #[test]
+    fn test_cell_list_without_open_cell_id() {
+        //the default OpenCelliD file does not exist, the measurements of the cell missing in the cell list are skipped instead
+        let args = connection_log_args("simulation_curator_cell_list_only", 1, &[1000, 1002], &[]);
+        assert!(!std::path::Path::new(&args.open_cell_id_data_loc).exists());
+        run_window(&args);
+    }
+
+    #[test]
+    fn test_scenario_error() {
+        //a missing input of one window is returned to the caller instead of aborting the run
+        let args = connection_log_args("simulation_curator_scenario_error", 1, &[1000, 1001], &["--link-model-path", "missing_link_model.json"]);
+        let window = ScenarioWindow { day_of_the_week: args.day_of_the_week.clone(), start_time: std::time::Duration::from_secs(28800), end_time: std::time::Duration::from_secs(29400) };
+        assert!(run_scenario(&args, &window, &mut SharedInputs::read(&args).unwrap()).is_err());
+    }
 }
@@ -0,0 +1,200 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
+use polars::prelude::*;
+use serde::Deserialize;
+use crate::cell_data::{read_cell_data_csv, CellGlobalId, MultiTripAndCellData, Radio, RadioCell, TripAndCellData};
+use crate::gtfs::{parse_duration, PartialBlock, ShapePoint};
+
+/// The route id of all vehicles read from a connection log
+pub const CONNECTION_LOG_ROUTE: &str = "connection_log";
+
+// one serving cell measurement, the position of the vehicle is optional
+#[derive(Deserialize)]
+struct ConnectionLogRecord {
+    timestamp: String,
+    vehicle: String,
+    radio: Radio,
+    mcc: u64,
+    mnc: u64,
+    area_code: u64,
+    cell_id: u64,
+    lat: Option<f64>,
+    lon: Option<f64>,
+}
+
+struct Measurement {
+    timestamp: Duration,
+    cell: CellGlobalId,
+    position: Option<(f64, f64)>,
+}
+
+#[derive(Deserialize)]
+struct CellListRecord {
+    radio: Radio,
+    mcc: u64,
+    mnc: u64,
+    area_code: u64,
+    cell_id: u64,
+    lat: f64,
+    lon: f64,
+    range: Option<f64>,
+}
+
+// timestamps are either a time of day (HH:MM:SS) or seconds since midnight
+fn parse_timestamp(timestamp: &str) -> Result<Duration, Box<dyn std::error::Error>> {
+    match timestamp.parse::<f64>() {
+        Ok(seconds) => Ok(Duration::from_secs_f64(seconds)),
+        Err(_) => parse_duration(timestamp),
+    }
+}
+
+/// Read the locations of radio cells from a csv file with the header "radio,mcc,mnc,area_code,cell_id,lat,lon,range".
+/// The range column may be empty.
+pub fn read_cell_list(path: &str) -> Result<HashMap<CellGlobalId, RadioCell>, Box<dyn std::error::Error>> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let mut cells = HashMap::new();
+    for record in reader.deserialize() {
+        let record: CellListRecord = record?;
+        let id = CellGlobalId { radio: record.radio, mcc: record.mcc, mnc: record.mnc, area_code: record.area_code, cell_id: record.cell_id };
+        cells.insert(id, RadioCell { lat: record.lat, lon: record.lon, id, range: record.range.unwrap_or(0.0) });
+    }
+    Ok(cells)
+}
+
+fn find_cells_in_open_cell_id_data(path: &str, cell_ids: &HashSet<CellGlobalId>) -> PolarsResult<HashMap<CellGlobalId, RadioCell>> {
+    let cids = Series::new("cid", cell_ids.iter().map(|id| id.cell_id).collect::<Vec<u64>>());
+    let df = read_cell_data_csv(path)?
+        .lazy()
+        .filter(col("cid").is_in(lit(cids)))
+        .collect()?;
+    let mut cells = HashMap::new();
+    for i in 0..df.height() {
+        let cell = RadioCell::from_row(&df, i);
+        if cell_ids.contains(&cell.id) {
+            cells.insert(cell.id, cell);
+        }
+    }
+    Ok(cells)
+}
+
+/// Build trips from a serving cell log with the header "timestamp,vehicle,radio,mcc,mnc,area_code,cell_id,lat,lon" instead of a schedule.
+/// Every vehicle becomes a block whose shape points are its measurements between the start and the end time. The locations of the
+/// cells are taken from the cell list and, for cells missing there, from the OpenCelliD data. Measurements without a known cell
+/// location are skipped. Vehicles without a position use the location of their serving cell.
+pub fn read_connection_log(log_path: &str, open_cell_id_path: Option<&str>, cell_list_path: Option<&str>, start_time: Duration, end_time: Duration) -> Result<MultiTripAndCellData, Box<dyn std::error::Error>> {
+    let mut reader = csv::Reader::from_path(log_path)?;
+    let mut measurements: BTreeMap<String, Vec<Measurement>> = BTreeMap::new();
+    for record in reader.deserialize() {
+        let record: ConnectionLogRecord = record?;
+        let timestamp = parse_timestamp(&record.timestamp)?;
+        if timestamp < start_time || timestamp > end_time {
+            continue;
+        }
+        let cell = CellGlobalId { radio: record.radio, mcc: record.mcc, mnc: record.mnc, area_code: record.area_code, cell_id: record.cell_id };
+        let position = record.lat.zip(record.lon);
+        measurements.entry(record.vehicle).or_default().push(Measurement { timestamp, cell, position });
+    }
+
+    //resolve the cell locations, the cell list takes precedence over OpenCelliD
+    let mut cell_ids: HashSet<CellGlobalId> = measurements.values().flatten().map(|m| m.cell).collect();
+    let mut radio_cells = match cell_list_path {
+        Some(path) => read_cell_list(path)?,
+        None => HashMap::new(),
+    };
+    radio_cells.retain(|id, _| cell_ids.contains(id));
+    cell_ids.retain(|id| !radio_cells.contains_key(id));
+    if let (Some(path), false) = (open_cell_id_path, cell_ids.is_empty()) {
+        let found = find_cells_in_open_cell_id_data(path, &cell_ids)?;
+        cell_ids.retain(|id| !found.contains_key(id));
+        radio_cells.extend(found);
+    }
+    if !cell_ids.is_empty() {
+        let mut missing: Vec<String> = cell_ids.iter().map(|id| id.to_string()).collect();
+        missing.sort();
+        println!("Skipping measurements of {} cells without a known location: {}", missing.len(), missing.join(", "));
+    }
+
+    let mut trips = vec![];
+    for (vehicle, mut vehicle_measurements) in measurements {
+        vehicle_measurements.sort_by_key(|m| m.timestamp);
+        let mut shape_points = vec![];
+        let mut cell_data = HashMap::new();
+        for measurement in vehicle_measurements {
+            let Some(cell) = radio_cells.get(&measurement.cell) else {
+                continue;
+            };
+            let (lat, lon) = measurement.position.unwrap_or((cell.lat, cell.lon));
+            let sequence = shape_points.len() as u64;
+            cell_data.insert((vehicle.clone(), sequence), measurement.cell);
+            shape_points.push(ShapePoint {
+                shape_id: vehicle.clone(),
+                shape_pt_lat: lat,
+                shape_pt_lon: lon,
+                shape_pt_sequence: sequence,
+                shape_dist_traveled: None,
+                time: Some(measurement.timestamp),
+                synthetic: false,
+            });
+        }
+        if shape_points.is_empty() {
+            continue;
+        }
+        trips.push(TripAndCellData {
+            trip: PartialBlock {
+                block_id: vehicle,
+                route_id: CONNECTION_LOG_ROUTE.to_string(),
+                stops: vec![],
+                shape_points,
+            },
+            cell_data,
+        });
+    }
+    println!("Connection log contains {} vehicles and {} radio cells", trips.len(), radio_cells.len());
+
+    Ok(MultiTripAndCellData {
+        trips: HashMap::from([(CONNECTION_LOG_ROUTE.to_string(), trips)]),
+        radio_cells,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_connection_log() {
+        let directory = std::env::temp_dir();
+        let log_path = directory.join("simulation_curator_connection_log.csv");
+        let cell_list_path = directory.join("simulation_curator_cell_list.csv");
+        std::fs::write(&log_path, "timestamp,vehicle,radio,mcc,mnc,area_code,cell_id,lat,lon
+08:00:10,bus1,LTE,262,2,100,1000,,
+08:00:00,bus1,LTE,262,2,100,1000,52.50,13.40
+08:00:20,bus1,LTE,262,2,100,1001,52.51,13.41
+09:00:00,bus1,LTE,262,2,100,1000,52.50,13.40
+28805,bus2,LTE,262,2,100,1001,52.52,13.42
+28825,bus2,LTE,262,2,100,9999,52.52,13.42
+").unwrap();
+        std::fs::write(&cell_list_path, "radio,mcc,mnc,area_code,cell_id,lat,lon,range
+LTE,262,2,100,1000,52.49,13.39,500
+LTE,262,2,100,1001,52.51,13.41,
+LTE,262,2,100,1002,52.52,13.42,
+").unwrap();
+        let data = read_connection_log(log_path.to_str().unwrap(), None, Some(cell_list_path.to_str().unwrap()), parse_duration("08:00:00").unwrap(), parse_duration("08:30:00").unwrap()).unwrap();
+
+        //only the cells that serve a vehicle are kept
+        let mut cells: Vec<u64> = data.radio_cells.keys().map(|id| id.cell_id).collect();
+        cells.sort();
+        assert_eq!(cells, vec![1000, 1001]);
+
+        let trips = data.trips.get(CONNECTION_LOG_ROUTE).unwrap();
+        assert_eq!(trips.iter().map(|t| t.trip.block_id.as_str()).collect::<Vec<_>>(), vec!["bus1", "bus2"]);
+        //measurements are sorted by time, a measurement without position is at its serving cell, the one after the end is dropped
+        let points: Vec<(u64, f64, f64)> = trips[0].trip.shape_points.iter().map(|p| (p.time.unwrap().as_secs(), p.shape_pt_lat, p.shape_pt_lon)).collect();
+        assert_eq!(points, vec![(28800, 52.50, 13.40), (28810, 52.49, 13.39), (28820, 52.51, 13.41)]);
+        let serving: Vec<u64> = (0..3).map(|sequence| trips[0].cell_data.get(&("bus1".to_string(), sequence)).unwrap().cell_id).collect();
+        assert_eq!(serving, vec![1000, 1000, 1001]);
+        //the measurement of the unknown cell is skipped
+        assert_eq!(trips[1].trip.shape_points.len(), 1);
+        assert_eq!(trips[1].cell_data.get(&("bus2".to_string(), 0)).unwrap().cell_id, 1001);
+    }
+}
@@ -0,0 +1,131 @@
+use std::time::Duration;
+use crate::nes_simulation::{ISQPEventAction, TopologyUpdate};
+
+const SEARCH_ITERATIONS: usize = 40;
+
+/// The rate of reconnects per second of emulated time that should be reached
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RateTarget {
+    Mean(f64),
+    Peak(f64),
+}
+
+impl RateTarget {
+    fn rate(&self) -> f64 {
+        match self {
+            RateTarget::Mean(rate) | RateTarget::Peak(rate) => *rate,
+        }
+    }
+
+    fn kind(&self) -> &str {
+        match self {
+            RateTarget::Mean(_) => "mean",
+            RateTarget::Peak(_) => "peak",
+        }
+    }
+}
+
+/// Number of reconnects in every second of the emulation
+#[derive(Clone, Debug)]
+pub struct RateDistribution {
+    pub reconnects_per_second: Vec<usize>,
+}
+
+impl RateDistribution {
+    /// Count the add events of the updates in every second of an emulation with the given runtime
+    pub fn from_updates(updates: &[TopologyUpdate], runtime: Duration) -> Self {
+        let last_update = updates.last().map(|u| u.timestamp.as_secs() + 1).unwrap_or(0);
+        let seconds = (runtime.as_secs_f64().ceil() as u64).max(last_update).max(1);
+        let mut reconnects_per_second = vec![0; seconds as usize];
+        for update in updates {
+            reconnects_per_second[update.timestamp.as_secs() as usize] += update.events.iter().filter(|e| e.action == ISQPEventAction::add).count();
+        }
+        RateDistribution { reconnects_per_second }
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.reconnects_per_second.iter().sum::<usize>() as f64 / self.reconnects_per_second.len() as f64
+    }
+
+    pub fn peak(&self) -> usize {
+        self.reconnects_per_second.iter().copied().max().unwrap_or(0)
+    }
+
+    pub fn percentile(&self, percentile: f64) -> usize {
+        let mut sorted = self.reconnects_per_second.clone();
+        sorted.sort();
+        let index = ((sorted.len() - 1) as f64 * percentile / 100.0).round() as usize;
+        sorted[index]
+    }
+
+    fn value(&self, target: RateTarget) -> f64 {
+        match target {
+            RateTarget::Mean(_) => self.mean(),
+            RateTarget::Peak(_) => self.peak() as f64,
+        }
+    }
+
+    pub fn print_summary(&self) {
+        println!("Reconnects per second over {}s: mean {:.2}, median {}, p90 {}, p99 {}, peak {}",
+                 self.reconnects_per_second.len(), self.mean(), self.percentile(50.0), self.percentile(90.0), self.percentile(99.0), self.peak());
+    }
+}
+
+// bisection over a parameter the rate grows with, returns the parameter whose rate is closest to the target
+fn bisect(target: RateTarget, mut low: f64, mut high: f64, geometric: bool, mut measure: impl FnMut(f64) -> RateDistribution) -> (f64, RateDistribution) {
+    let mut best: Option<(f64, RateDistribution)> = None;
+    for _ in 0..SEARCH_ITERATIONS {
+        let candidate = if geometric { (low * high).sqrt() } else { (low + high) / 2.0 };
+        let distribution = measure(candidate);
+        let value = distribution.value(target);
+        let is_better = match &best {
+            Some((_, best_distribution)) => (value - target.rate()).abs() < (best_distribution.value(target) - target.rate()).abs(),
+            None => true,
+        };
+        if value < target.rate() {
+            low = candidate;
+        } else {
+            high = candidate;
+        }
+        if is_better {
+            best = Some((candidate, distribution));
+        }
+    }
+    best.unwrap()
+}
+
+/// Find the speedup of the schedule at which the reconnect rate is closest to the target
+pub fn search_speedup(target: RateTarget, measure: impl FnMut(f64) -> RateDistribution) -> (f64, RateDistribution) {
+    let (speedup, distribution) = bisect(target, 0.01, 10000.0, true, measure);
+    println!("Speedup {:.2} reaches a {} rate of {:.2} reconnects per second (target {:.2})", speedup, target.kind(), distribution.value(target), target.rate());
+    (speedup, distribution)
+}
+
+/// Find the fraction of vehicles for which the reconnect rate is closest to the target
+pub fn search_sampling_fraction(target: RateTarget, measure: impl FnMut(f64) -> RateDistribution) -> (f64, RateDistribution) {
+    let (fraction, distribution) = bisect(target, 0.0, 1.0, false, measure);
+    println!("Sampling {:.1}% of the vehicles reaches a {} rate of {:.2} reconnects per second (target {:.2})", fraction * 100.0, target.kind(), distribution.value(target), target.rate());
+    (fraction, distribution)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_speedup() {
+        //100 reconnects spread over 1000 seconds of real time
+        let measure = |speedup: f64| {
+            let runtime = Duration::from_secs_f64(1000.0 / speedup);
+            let seconds = runtime.as_secs_f64().ceil() as usize;
+            let mut reconnects_per_second = vec![0; seconds.max(1)];
+            for i in 0..100 {
+                reconnects_per_second[((i * 10) as f64 / speedup) as usize] += 1;
+            }
+            RateDistribution { reconnects_per_second }
+        };
+        let (speedup, distribution) = search_speedup(RateTarget::Mean(2.0), measure);
+        assert!((distribution.mean() - 2.0).abs() < 0.05);
+        assert!((speedup - 20.0).abs() < 0.5);
+    }
+}
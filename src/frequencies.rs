@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use rusqlite::Connection;
+use crate::gtfs::{duration_to_string, parse_duration, TripSchedule};
+
+/// A period in which a template trip is served with a fixed headway, as given by frequencies.txt
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frequency {
+    pub trip_id: String,
+    pub start_time: Duration,
+    pub end_time: Duration,
+    pub headway: Duration,
+    /// Whether the feed declares the departures as exact. Both kinds are expanded into exact departures.
+    pub exact_times: bool,
+}
+
+/// Read the frequencies of all template trips. Feeds without a frequencies table have no templates.
+pub fn read_frequencies(db: &Connection) -> rusqlite::Result<HashMap<String, Vec<Frequency>>, Box<dyn std::error::Error>> {
+    let table_count: u64 = db.query_row("SELECT count(*) FROM sqlite_master WHERE type='table' AND name='frequencies'", [], |row| row.get(0))?;
+    let mut frequencies: HashMap<String, Vec<Frequency>> = HashMap::new();
+    if table_count == 0 {
+        return Ok(frequencies);
+    }
+    let mut stmt = db.prepare("SELECT trip_id, start_time, end_time, headway_secs, exact_times FROM frequencies")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<usize, String>(0)?, row.get::<usize, String>(1)?, row.get::<usize, String>(2)?, row.get::<usize, u64>(3)?, row.get::<usize, Option<u64>>(4)?))
+    })?;
+    for row in rows {
+        let (trip_id, start_time, end_time, headway_secs, exact_times) = row?;
+        if headway_secs == 0 {
+            println!("Skipping frequency of trip {} without a headway", trip_id);
+            continue;
+        }
+        frequencies.entry(trip_id.clone()).or_default().push(Frequency {
+            trip_id,
+            start_time: parse_duration(&start_time)?,
+            end_time: parse_duration(&end_time)?,
+            headway: Duration::from_secs(headway_secs),
+            exact_times: exact_times == Some(1),
+        });
+    }
+    Ok(frequencies)
+}
+
+/// Create a concrete trip for every departure of a template trip whose run overlaps the time window. Departures start at the
+/// start time of each frequency and repeat with its headway until before its end time. The stop times of the template are
+/// shifted so that the template departs from its first stop at the departure time. The id of a run is the template trip id
+/// followed by its departure time.
+pub fn expand_frequencies(template: &TripSchedule, frequencies: &[Frequency], start_time: Duration, end_time: Duration) -> rusqlite::Result<Vec<TripSchedule>, Box<dyn std::error::Error>> {
+    let (Some(first_stop), Some(last_stop)) = (template.stops.first(), template.stops.last()) else {
+        return Ok(vec![]);
+    };
+    let template_departure = parse_duration(&first_stop.departure_time)?;
+    let run_duration = parse_duration(&last_stop.arrival_time)?.saturating_sub(template_departure);
+
+    let mut runs = vec![];
+    for frequency in frequencies {
+        let mut departure = frequency.start_time;
+        while departure < frequency.end_time {
+            if departure <= end_time && departure + run_duration >= start_time {
+                runs.push(shift_trip(template, departure, template_departure)?);
+            }
+            departure += frequency.headway;
+        }
+    }
+    Ok(runs)
+}
+
+fn shift_trip(template: &TripSchedule, departure: Duration, template_departure: Duration) -> rusqlite::Result<TripSchedule, Box<dyn std::error::Error>> {
+    let trip_id = format!("{}_{}", template.trip_id, duration_to_string(departure));
+    let shift = |time: &str| -> rusqlite::Result<String, Box<dyn std::error::Error>> {
+        Ok(duration_to_string(parse_duration(time)? + departure - template_departure))
+    };
+    let mut stops = template.stops.clone();
+    for stop in stops.iter_mut() {
+        stop.trip_id = trip_id.clone();
+        stop.arrival_time = shift(&stop.arrival_time)?;
+        stop.departure_time = shift(&stop.departure_time)?;
+    }
+    Ok(TripSchedule { trip_id, stops, shape_points: template.shape_points.clone() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_frequencies() {
+        let template = TripSchedule::test("template", &[("00:00:00", 13.4, "00:00:00"), ("00:20:00", 13.4, "00:20:00")]);
+        let frequency = Frequency { trip_id: "template".to_string(), start_time: Duration::from_secs(6 * 3600), end_time: Duration::from_secs(10 * 3600), headway: Duration::from_secs(600), exact_times: true };
+        //runs departing between 07:40 and 09:00 overlap the window
+        let runs = expand_frequencies(&template, &[frequency], Duration::from_secs(8 * 3600), Duration::from_secs(9 * 3600)).unwrap();
+        assert_eq!(runs.len(), 9);
+        assert_eq!(runs[0].trip_id, "template_07:40:00");
+        assert_eq!(runs[0].stops[1].arrival_time, "08:00:00");
+        assert_eq!(runs[8].stops[0].departure_time, "09:00:00");
+    }
+}
@@ -1,10 +1,9 @@
 use geojson::{Feature, GeoJson, Geometry, Value};
 use std::time::Duration;
-use rusqlite::{Connection, named_params};
-use std::collections::{BTreeMap};
 use std::hash::{Hash, Hasher};
-use geo::VincentyDistance;
-use crate::colors;
+use crate::{colors, map_matching};
+use crate::shape_synthesis::ShapeSynthesis;
+use crate::movement::{MovementProfile, TripMovement};
 
 #[derive(Clone, Debug)]
 pub struct PartialBlock {
@@ -27,13 +26,19 @@ pub fn get_shape_points_from_trips(trips: &[PartialBlock]) -> Vec<ShapePoint> {
 
 #[derive(Clone, Debug)]
 pub struct Stop {
-    trip_id: String,
+    pub trip_id: String,
     pub stop_id: String,
     pub stop_name: String,
     pub arrival_time: String,
     pub departure_time: String,
     pub lat: f64,
     pub lon: f64,
+    /// The stop_sequence of the stop time in the feed, increasing along the trip but not necessarily consecutive
+    pub stop_sequence: u64,
+    /// Distance along the shape of the trip as given by the feed
+    pub shape_dist_traveled: Option<f64>,
+    /// Distance in meters between the stop and the shape point it was matched to
+    pub match_distance: Option<f64>,
 }
 
 #[derive(Clone, Debug)]
@@ -42,7 +47,10 @@ pub struct ShapePoint {
     pub(crate) shape_pt_lat: f64,
     pub(crate) shape_pt_lon: f64,
     pub shape_pt_sequence: u64,
+    pub shape_dist_traveled: Option<f64>,
     pub time: Option<Duration>,
+    /// The point was not part of the feed but created from the stops, a polyline or at a boundary of the time window
+    pub synthetic: bool,
 }
 
 impl Eq for ShapePoint {}
@@ -63,6 +71,9 @@ fn feature_line_from_shape_points(shape_points: &Vec<ShapePoint>) -> Feature {
     let mut properties = geojson::JsonObject::new();
     if !shape_points.is_empty() {
         properties.insert("shape_id".to_string(), serde_json::Value::String((&shape_points[0]).shape_id.clone()));
+        if shape_points.iter().any(|p| p.synthetic) {
+            properties.insert("synthetic".to_string(), true.into());
+        }
     } else {
         panic!("No shape points found for trip");
     }
@@ -89,6 +100,9 @@ impl ShapePoint {
         if let Some(time) = self.time {
             properties.insert("time".to_string(), serde_json::Value::String(duration_to_string(time)));
         }
+        if self.synthetic {
+            properties.insert("synthetic".to_string(), true.into());
+        }
         Feature {
             bbox: None,
             geometry: Some(Geometry::new(Value::Point(vec![self.shape_pt_lon, self.shape_pt_lat]))),
@@ -151,6 +165,9 @@ impl Stop {
         properties.insert("departure_time".to_string(), serde_json::Value::String(self.departure_time.clone()));
         //insert trip id
         properties.insert("trip_id".to_string(), serde_json::Value::String(self.trip_id.clone()));
+        if let Some(match_distance) = self.match_distance {
+            properties.insert("match_distance".to_string(), match_distance.into());
+        }
         if let Some(color) = color {
             properties.insert("marker-color".to_string(), serde_json::Value::String(color.to_string()));
         }
@@ -165,7 +182,7 @@ impl Stop {
     }
 }
 
-fn duration_to_string(duration: Duration) -> String {
+pub(crate) fn duration_to_string(duration: Duration) -> String {
     let total_seconds = duration.as_secs();
     let hours = total_seconds / 3600;
     let minutes = (total_seconds % 3600) / 60;
@@ -186,196 +203,104 @@ pub fn parse_duration(time_str: &str) -> rusqlite::Result<Duration, Box<dyn std:
     Ok(Duration::new(hours * 3600 + minutes * 60 + seconds, 0))
 }
 
-//read the stops for a trip
-pub fn read_stops_for_block(block_id: String, route_id: String, db: &Connection, start_time: Duration, end_time: Duration) -> rusqlite::Result<Option<PartialBlock>, Box<dyn std::error::Error>> {
-    println!("reading stops for block {}", block_id);
-    let mut stmt = db.prepare("SELECT DISTINCT trip_id FROM trips WHERE trips.block_id=:block_id")?;
-    let trip_ids = stmt.query_map(named_params! {":block_id": block_id}, |row| {
-        Ok(row.get::<usize, String>(0))
-    })?;
+/// The stops and the shape of a single trip as stored in the feed. Stops are ordered by their stop sequence and shape points
+/// by their shape sequence, shape points have no time yet.
+#[derive(Clone, Debug)]
+pub struct TripSchedule {
+    pub trip_id: String,
+    pub stops: Vec<Stop>,
+    pub shape_points: Vec<ShapePoint>,
+}
 
-    let mut all_stops_in_range = vec![];
-    let mut all_shape_points = vec![];
-    for trip in trip_ids {
-        let trip = trip??;
-        let mut stmt = db.prepare("SELECT stops.stop_id, arrival_time, departure_time, stop_name FROM stop_times LEFT JOIN stops ON stops.stop_id=stop_times.stop_id WHERE trip_id=:trip_id ")?;
-
-        let stop_times = stmt.query_map(named_params! {":trip_id": &trip}, |row| {
-            Ok((row.get::<usize, String>(0), row.get::<usize, String>(1), row.get::<usize, String>(2), row.get::<usize, String>(3)))
-        })?;
-
-        let mut stops = BTreeMap::new();
-        for stop_time in stop_times {
-            let (stop_id, arrival_time, departure_time, stop_name) = stop_time.unwrap();
-            let stop_id = stop_id.unwrap();
-            let arrival_time = arrival_time.unwrap();
-            let departure_time = departure_time.unwrap();
-            let arrival_time = parse_duration(&arrival_time)?;
-            let departure_time = parse_duration(&departure_time)?;
-            let stop_name = stop_name.unwrap();
-            // if arrival_time < start_time || departure_time > end_time {
-            //     continue;
-            // }
-            let mut stmt = db.prepare("SELECT stop_lat, stop_lon FROM stops WHERE stop_id=:stop_id")?;
-            let stop_coords = stmt.query_map(named_params! {":stop_id": stop_id}, |row| {
-                Ok((row.get::<usize, f64>(0), row.get::<usize, f64>(1)))
-            })?;
-            for stop_coord in stop_coords {
-                let (lat, lon) = stop_coord.unwrap();
-                let lat = lat.unwrap();
-                let lon = lon.unwrap();
-                stops.insert(arrival_time, Stop {
-                    trip_id: trip.clone(),
-                    stop_id: stop_id.clone(),
-                    arrival_time: duration_to_string(arrival_time),
-                    departure_time: duration_to_string(departure_time),
-                    lat,
-                    lon,
-                    stop_name: stop_name.clone(),
-                });
-            }
-        }
-        // println!("stops in list: {}", stops.len());
-
-        // iterate over the collected stops and check if they are in the time range or preceed or succeed a stop in the range
-        let mut stops = stops.into_iter().collect::<Vec<_>>();
-        stops.sort_by_key(|(time, _)| *time);
-        let mut stops_in_range = Vec::new();
-        for i in 0..stops.len() {
-            let (_, stop) = stops[i].clone();
-            let arrival_time = parse_duration(&stop.arrival_time)?;
-            let departure_time = parse_duration(&stop.departure_time)?;
-            if departure_time >= start_time && arrival_time <= end_time {
-                stops_in_range.push(stop);
-            } else if i > 0 && i < stops.len() - 1 {
-                let (_, prev_stop) = stops[i - 1].clone();
-                let prev_time = parse_duration(&prev_stop.departure_time)?;
-                let (_, next_stop) = stops[i + 1].clone();
-                let next_time = parse_duration(&next_stop.arrival_time)?;
-                if (prev_time < end_time && arrival_time > end_time) || (next_time > start_time && departure_time < start_time) {
-                    stops_in_range.push(stop);
-                }
-            }
-        }
-        if stops_in_range.is_empty() {
-            continue
-        }
+/// Stops matched further away from their shape than this are reported
+const MATCH_DISTANCE_WARNING: f64 = 500.0;
+
+// a point at the boundary of the time window. Cells are assigned per shape id and sequence number, so the point gets a shape
+// id of its own for every block.
+fn boundary_point(point: &ShapePoint, position: (f64, f64), time: Duration, boundary: &str) -> ShapePoint {
+    ShapePoint {
+        shape_id: boundary.to_string(),
+        shape_pt_lat: position.0,
+        shape_pt_lon: position.1,
+        shape_pt_sequence: point.shape_pt_sequence,
+        shape_dist_traveled: None,
+        time: Some(time),
+        synthetic: true,
+    }
+}
 
-        let mut stmt = db.prepare("SELECT shape_id, shape_pt_lat, shape_pt_lon, shape_pt_sequence FROM shapes WHERE shape_id IN (SELECT shape_id FROM trips WHERE trip_id=:trip_id)")?;
-        let shape_points = stmt.query_map(named_params! {":trip_id": &trip}, |row| {
-            Ok(ShapePoint {
-                shape_id: row.get::<usize, String>(0).unwrap(),
-                shape_pt_lat: row.get::<usize, f64>(1).unwrap(),
-                shape_pt_lon: row.get::<usize, f64>(2).unwrap(),
-                shape_pt_sequence: row.get::<usize, u64>(3).unwrap(),
-                time: None,
-            })
-        })?;
-        let mut shape_points = shape_points.map(|x| x.unwrap()).collect::<Vec<_>>();
-        //sort shape points by time
-        shape_points.sort_by_key(|x| x.shape_pt_sequence);
-
-
-        // store the shape points in a btree map indexed on the shape sequence number
-        let mut shape_points_map = BTreeMap::new();
-        for shape_point in &mut shape_points {
-            shape_points_map.insert(shape_point.shape_pt_sequence, shape_point);
+// the position of the vehicle of a block at a time, either moving on a trip or waiting at the last stop of its previous trip
+// if its next trip has not started yet. The trips are ordered by time.
+fn block_position_at(trips: &[(TripMovement, Vec<ShapePoint>)], time: Duration, boundary: &str) -> Option<ShapePoint> {
+    for (i, (movement, points)) in trips.iter().enumerate() {
+        if let Some(position) = movement.position_at(time) {
+            let next = points.iter().find(|p| p.time >= Some(time)).unwrap_or(points.last()?);
+            return Some(boundary_point(next, position, time, boundary));
         }
-
-        let mut first_index = None;
-        let mut circular_end_time_tuple = None;
-
-        // iterate over the stops in the range and try to find a matching shape point
-        for (_, stop) in &stops {
-            let mut closest_shape_point_sequence = None;
-            let mut closest_distance = f64::MAX;
-            for (_, shape_point) in shape_points_map.iter() {
-                let stop_point = geo::Point::new(stop.lon, stop.lat);
-                let shape_point_geo = geo::Point::new(shape_point.shape_pt_lon, shape_point.shape_pt_lat);
-                let distance = shape_point_geo.vincenty_distance(&stop_point).unwrap();
-                if distance < closest_distance {
-                    closest_distance = distance;
-                    closest_shape_point_sequence = Some(shape_point.shape_pt_sequence);
-                }
-            }
-            if let Some(closest_shape_point) = closest_shape_point_sequence {
-                let shape_point = shape_points_map.get_mut(&closest_shape_point).unwrap();
-                let stop_center_time = (parse_duration(&stop.arrival_time).unwrap().as_millis() + parse_duration(&stop.departure_time).unwrap().as_millis()) / 2;
-                if let Some(time) = shape_point.time {
-                    circular_end_time_tuple = Some((time, Duration::from_millis(stop_center_time as u64)));
-                }
-                (*shape_point).time = Some(Duration::from_millis(stop_center_time as u64));
-                if first_index.is_none() {
-                    first_index = Some(shape_point.shape_pt_sequence);
-                }
-            }
+        let previous_end = points.last()?;
+        let next_start = trips.get(i + 1).and_then(|(_, next_points)| next_points.first());
+        if previous_end.time < Some(time) && next_start.is_some_and(|p| p.time > Some(time)) {
+            return Some(boundary_point(previous_end, (previous_end.shape_pt_lat, previous_end.shape_pt_lon), time, boundary));
         }
+    }
+    None
+}
 
-        //iterate over shape points and interpolate times
-        let mut last_time_index = None;
-
-        for (i, shape_point) in shape_points.clone().iter().enumerate() {
-            //when a shape point with a time is found, start counting the points until the next time
-            if let Some(time) = shape_point.time {
-                if let Some(last_time_index) = last_time_index {
-                    let last_time_elem: &ShapePoint = &shape_points[last_time_index];
-                    let mut last_time = last_time_elem.time.unwrap();
-                    let next_time = time;
-                    let time_diff = if let Some(time_diff) = next_time.checked_sub(last_time) {
-                        time_diff
-                    } else {
-                        let (first_time, sec_time): (Duration, Duration) = circular_end_time_tuple.expect("No circular end time");
-                        assert_eq!(sec_time, last_time);
-                        last_time = first_time;
-                        next_time.checked_sub(last_time).unwrap()
-                    };
-                    assert!(time_diff > Duration::from_secs(0));
-                    let num_points = i - last_time_index;
-                    let time_diff_per_point = time_diff / num_points as u32;
-                    for j in last_time_index + 1..i {
-                        let time = last_time + time_diff_per_point * (j - last_time_index) as u32;
-                        shape_points[j].time = Some(time);
-                    }
+/// Build the trajectory of a block from the schedules of its trips. The stops are matched to the shape of their trip, the
+/// shape points in between get the times at which a vehicle with the movement profile passes them and the trajectory is
+/// clipped to the time window. A vehicle that is on its way or waiting for its next trip at the start or the end of the window
+/// gets a point at its exact position at that time. Trips without a shape are skipped unless a shape synthesis is given.
+pub fn build_partial_block(block_id: String, route_id: String, trips: Vec<TripSchedule>, start_time: Duration, end_time: Duration, shape_synthesis: Option<&ShapeSynthesis>, profile: MovementProfile) -> rusqlite::Result<Option<PartialBlock>, Box<dyn std::error::Error>> {
+    let mut all_stops_in_range = vec![];
+    let mut movements = vec![];
+    for trip in trips {
+        let TripSchedule { trip_id, mut stops, mut shape_points } = trip;
+        if shape_points.is_empty() {
+            match shape_synthesis {
+                Some(shape_synthesis) => shape_points = shape_synthesis.synthesize_shape(&trip_id, &route_id, &stops),
+                None => {
+                    println!("No shape found for trip {}", trip_id);
+                    continue
                 }
-                last_time_index = Some(i);
             }
         }
-
-        //wrap around
-        if let Some((first_time, sec_time)) = circular_end_time_tuple {
-            for (i, p) in shape_points.iter().enumerate().rev() {
-                if let Some(time) = p.time {
-                    for j in i + 1..shape_points.len() {
-                        assert!(shape_points[j].time.is_none());
-                        let time_diff = sec_time.checked_sub(time).unwrap();
-                        let num_points = shape_points.len() - i;
-                        let time_diff_per_point = time_diff / num_points as u32;
-                        let time = first_time + time_diff_per_point * (j - i) as u32;
-                        shape_points[j].time = Some(time);
-                    }
-                    break;
-                }
-            }
+        let matches = map_matching::match_stops_to_shape(&stops, &shape_points);
+        for (stop, stop_match) in stops.iter_mut().zip(&matches) {
+            stop.match_distance = Some(stop_match.distance);
         }
-
-        //filter out the shape points that are outside the time window
-        shape_points.retain(|x| {
-            if let Some(time) = x.time {
-                time >= start_time && time <= end_time
-            } else {
-                false
+        if let Some(furthest) = matches.iter().map(|m| m.distance).max_by(f64::total_cmp) {
+            if furthest > MATCH_DISTANCE_WARNING {
+                println!("Trip {} has a stop {:.0}m away from its shape", trip_id, furthest);
             }
-        });
+        }
 
+        //the vehicle dwells at the stops and accelerates, cruises and brakes in between
+        let movement = TripMovement::new(&stops, &matches, &shape_points, profile)?;
+        let shape_points = movement.timed_points();
         if shape_points.is_empty() {
-            println!("No shape points in time window found for trip {}", block_id);
             continue
         }
-        all_stops_in_range.append(&mut stops_in_range);
-        all_shape_points.append(&mut shape_points);
+
+        for stop in stops {
+            if parse_duration(&stop.departure_time)? >= start_time && parse_duration(&stop.arrival_time)? <= end_time {
+                all_stops_in_range.push(stop);
+            }
+        }
+        movements.push((movement, shape_points));
+    }
+    movements.sort_by_key(|(_, points)| points.first().and_then(|p| p.time));
+
+    //only the points strictly inside the window are kept, the boundaries get interpolated points
+    let start_point = block_position_at(&movements, start_time, &format!("window_start_{}", block_id));
+    let end_point = block_position_at(&movements, end_time, &format!("window_end_{}", block_id));
+    let mut all_shape_points: Vec<ShapePoint> = start_point.into_iter().collect();
+    for (_, shape_points) in movements {
+        all_shape_points.extend(shape_points.into_iter().filter(|p| p.time.is_some_and(|time| time > start_time && time < end_time)));
     }
+    all_shape_points.extend(end_point);
+
     if all_shape_points.is_empty() {
-        println!("No shape points in time window found for trip {}", block_id);
+        println!("No shape points in time window found for block {}", block_id);
         return Ok(None);
     }
 
@@ -389,4 +314,93 @@ pub fn read_stops_for_block(block_id: String, route_id: String, db: &Connection,
             }
         )
     )
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+impl Stop {
+    /// A stop of a trip for tests
+    pub fn test(trip_id: &str, stop_id: &str, (lat, lon): (f64, f64), arrival_time: &str, departure_time: &str) -> Self {
+        Stop {
+            trip_id: trip_id.to_string(),
+            stop_id: stop_id.to_string(),
+            stop_name: stop_id.to_string(),
+            arrival_time: arrival_time.to_string(),
+            departure_time: departure_time.to_string(),
+            lat,
+            lon,
+            stop_sequence: 0,
+            shape_dist_traveled: None,
+            match_distance: None,
+        }
+    }
+}
+
+#[cfg(test)]
+impl ShapePoint {
+    /// An untimed point of a shape from the feed for tests
+    pub fn test(shape_id: &str, shape_pt_sequence: u64, (lat, lon): (f64, f64)) -> Self {
+        ShapePoint {
+            shape_id: shape_id.to_string(),
+            shape_pt_lat: lat,
+            shape_pt_lon: lon,
+            shape_pt_sequence,
+            shape_dist_traveled: None,
+            time: None,
+            synthetic: false,
+        }
+    }
+}
+
+#[cfg(test)]
+impl TripSchedule {
+    /// A trip without a shape whose stops, given as (stop id, longitude, time), lie on the equator and are numbered from 1
+    pub fn test(trip_id: &str, stops: &[(&str, f64, &str)]) -> Self {
+        TripSchedule {
+            trip_id: trip_id.to_string(),
+            stops: stops.iter().enumerate()
+                .map(|(i, (stop_id, lon, time))| Stop { stop_sequence: i as u64 + 1, ..Stop::test(trip_id, stop_id, (0.0, *lon), time, time) })
+                .collect(),
+            shape_points: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movement::VehicleKind;
+
+    fn trip(trip_id: &str, stops: &[(&str, f64, &str)]) -> TripSchedule {
+        let shape_points = (0..3).map(|i| {
+            let lon = if stops[0].1 == 0.0 { i as f64 * 0.01 } else { 0.02 - i as f64 * 0.01 };
+            ShapePoint::test(trip_id, i, (0.0, lon))
+        }).collect();
+        TripSchedule { shape_points, ..TripSchedule::test(trip_id, stops) }
+    }
+
+    #[test]
+    fn test_clip_at_window_boundaries() {
+        let trips = vec![
+            trip("back", &[("B", 0.02, "08:15:00"), ("A", 0.0, "08:25:00")]),
+            trip("out", &[("A", 0.0, "08:00:00"), ("B", 0.02, "08:10:00")]),
+        ];
+        let (start_time, end_time) = (parse_duration("08:05:00").unwrap(), parse_duration("08:12:00").unwrap());
+        let block = build_partial_block("b1".to_string(), "S41".to_string(), trips.clone(), start_time, end_time, None, VehicleKind::Tram.profile()).unwrap().unwrap();
+        let points: Vec<(&str, f64, u64)> = block.shape_points.iter()
+            .filter(|p| !p.shape_id.starts_with("sampled_"))
+            .map(|p| (p.shape_id.as_str(), (p.shape_pt_lon * 1000.0).round() / 1000.0, p.time.unwrap().as_secs()))
+            .collect();
+        //the vehicle is halfway at the start and waits for its next trip at the end of the window
+        assert_eq!(points, vec![("window_start_b1", 0.01, 29100), ("out", 0.02, 29400), ("window_end_b1", 0.02, 29520)]);
+        //its position is sampled between the shape points, which are about 1.1km apart
+        assert!(block.shape_points.len() > 10);
+        assert!(block.shape_points.windows(2).all(|pair| pair[0].time <= pair[1].time));
+        assert_eq!(block.stops.iter().map(|s| s.stop_id.as_str()).collect::<Vec<_>>(), vec!["B"]);
+
+        //a vehicle between two stops for the whole window is kept without stops
+        let (start_time, end_time) = (parse_duration("08:02:00").unwrap(), parse_duration("08:08:00").unwrap());
+        let block = build_partial_block("b1".to_string(), "S41".to_string(), trips, start_time, end_time, None, VehicleKind::Tram.profile()).unwrap().unwrap();
+        assert!(block.stops.is_empty());
+        assert!(block.shape_points.len() >= 2);
+    }
+}
@@ -0,0 +1,443 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::time::Duration;
+use chrono::{NaiveDate, TimeZone};
+use chrono_tz::Tz;
+use prost::Message;
+use crate::gtfs::{duration_to_string, parse_duration, PartialBlock, ShapePoint, TripSchedule};
+
+const SECONDS_PER_DAY: i64 = 24 * 3600;
+
+// the subset of gtfs-realtime.proto that is needed to replay vehicle positions and trip updates, unknown fields are skipped
+#[derive(Clone, PartialEq, Message)]
+struct FeedMessage {
+    #[prost(message, required, tag = "1")]
+    header: FeedHeader,
+    #[prost(message, repeated, tag = "2")]
+    entity: Vec<FeedEntity>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct FeedHeader {
+    #[prost(string, required, tag = "1")]
+    gtfs_realtime_version: String,
+    #[prost(uint64, optional, tag = "3")]
+    timestamp: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct FeedEntity {
+    #[prost(string, required, tag = "1")]
+    id: String,
+    #[prost(bool, optional, tag = "2")]
+    is_deleted: Option<bool>,
+    #[prost(message, optional, tag = "3")]
+    trip_update: Option<TripUpdate>,
+    #[prost(message, optional, tag = "4")]
+    vehicle: Option<VehiclePosition>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TripDescriptor {
+    #[prost(string, optional, tag = "1")]
+    trip_id: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    start_date: Option<String>,
+    #[prost(string, optional, tag = "5")]
+    route_id: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct VehicleDescriptor {
+    #[prost(string, optional, tag = "1")]
+    id: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    label: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TripUpdate {
+    #[prost(message, required, tag = "1")]
+    trip: TripDescriptor,
+    #[prost(message, repeated, tag = "2")]
+    stop_time_update: Vec<StopTimeUpdate>,
+    #[prost(message, optional, tag = "3")]
+    vehicle: Option<VehicleDescriptor>,
+    #[prost(uint64, optional, tag = "4")]
+    timestamp: Option<u64>,
+    #[prost(int32, optional, tag = "5")]
+    delay: Option<i32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct StopTimeEvent {
+    #[prost(int32, optional, tag = "1")]
+    delay: Option<i32>,
+    #[prost(int64, optional, tag = "2")]
+    time: Option<i64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct StopTimeUpdate {
+    #[prost(uint32, optional, tag = "1")]
+    stop_sequence: Option<u32>,
+    #[prost(message, optional, tag = "2")]
+    arrival: Option<StopTimeEvent>,
+    #[prost(message, optional, tag = "3")]
+    departure: Option<StopTimeEvent>,
+    #[prost(string, optional, tag = "4")]
+    stop_id: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Position {
+    #[prost(float, required, tag = "1")]
+    latitude: f32,
+    #[prost(float, required, tag = "2")]
+    longitude: f32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct VehiclePosition {
+    #[prost(message, optional, tag = "1")]
+    trip: Option<TripDescriptor>,
+    #[prost(message, optional, tag = "2")]
+    position: Option<Position>,
+    #[prost(uint64, optional, tag = "5")]
+    timestamp: Option<u64>,
+    #[prost(message, optional, tag = "8")]
+    vehicle: Option<VehicleDescriptor>,
+}
+
+/// A position of a vehicle at a time of the day
+#[derive(Clone, Debug, PartialEq)]
+pub struct VehicleObservation {
+    pub trip_id: String,
+    pub time: Duration,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+/// The change of the arrival and departure at a stop. Absolute times take precedence over delays.
+#[derive(Clone, Debug, PartialEq)]
+struct StopDelay {
+    stop_sequence: Option<u32>,
+    stop_id: Option<String>,
+    arrival: Option<StopTime>,
+    departure: Option<StopTime>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum StopTime {
+    Delay(i64),
+    Time(Duration),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct TripDelay {
+    timestamp: u64,
+    delay: i64,
+    stops: Vec<StopDelay>,
+}
+
+/// The service date a recorded feed is replayed for. Times of the day of the schedule count from noon minus 12 hours of the
+/// date in the time zone of the agency, which is midnight except on the days of a daylight saving time change.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServiceDay {
+    //the date as YYYYMMDD like the start date of a trip descriptor
+    date: String,
+    //posix timestamp at which the times of the day of the date start
+    start: i64,
+}
+
+impl ServiceDay {
+    /// The service day of a date given as YYYYMMDD in an IANA time zone, e.g. Europe/Berlin
+    pub fn new(date: &str, timezone: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let timezone: Tz = timezone.parse().map_err(|e| format!("Invalid time zone {}: {}", timezone, e))?;
+        let noon = NaiveDate::parse_from_str(date, "%Y%m%d")?.and_hms_opt(12, 0, 0).unwrap();
+        let noon = timezone.from_local_datetime(&noon).single().ok_or(format!("Noon of {} is ambiguous in {}", date, timezone))?;
+        Ok(ServiceDay { date: date.to_string(), start: noon.timestamp() - SECONDS_PER_DAY / 2 })
+    }
+
+    // the time of the day of a posix timestamp, times after the end of the day are kept as times after 24:00:00
+    fn time_of_day(&self, timestamp: i64) -> Option<Duration> {
+        (timestamp >= self.start).then(|| Duration::from_secs((timestamp - self.start) as u64))
+    }
+
+    // a trip with a start date belongs to the day if it started on the date, otherwise if it was recorded during the day
+    fn contains(&self, trip: &TripDescriptor, timestamp: i64) -> bool {
+        match &trip.start_date {
+            Some(start_date) => *start_date == self.date,
+            None => (self.start..self.start + SECONDS_PER_DAY).contains(&timestamp),
+        }
+    }
+}
+
+/// Vehicle positions and trip updates recorded from a GTFS-Realtime feed
+#[derive(Clone, Debug, Default)]
+pub struct RealtimeArchive {
+    /// The observations of every vehicle ordered by time
+    pub positions: BTreeMap<String, Vec<VehicleObservation>>,
+    //the latest update of every trip
+    trip_delays: HashMap<String, TripDelay>,
+}
+
+fn stop_time(event: &Option<StopTimeEvent>, service_day: &ServiceDay) -> Option<StopTime> {
+    let event = event.as_ref()?;
+    match (event.time, event.delay) {
+        (Some(time), _) => service_day.time_of_day(time).map(StopTime::Time),
+        (None, Some(delay)) => Some(StopTime::Delay(delay as i64)),
+        (None, None) => None,
+    }
+}
+
+impl RealtimeArchive {
+    /// Read all .pb files of a directory, each containing a serialized FeedMessage. Only positions and updates of trips of the
+    /// service day are kept, their timestamps are converted to times of that day.
+    pub fn read_directory(path: &str, service_day: &ServiceDay) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut files: Vec<_> = fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        files.retain(|file| file.extension().is_some_and(|extension| extension == "pb"));
+        files.sort();
+
+        let mut archive = RealtimeArchive::default();
+        for file in &files {
+            archive.add_feed(&fs::read(file)?, service_day)?;
+        }
+        archive.positions.values_mut().for_each(|observations| {
+            observations.sort_by_key(|o| o.time);
+            observations.dedup_by_key(|o| o.time);
+        });
+        println!("Read {} realtime files with positions of {} vehicles and updates of {} trips", files.len(), archive.positions.len(), archive.trip_delays.len());
+        Ok(archive)
+    }
+
+    fn add_feed(&mut self, bytes: &[u8], service_day: &ServiceDay) -> Result<(), Box<dyn std::error::Error>> {
+        let feed = FeedMessage::decode(bytes)?;
+        let feed_timestamp = feed.header.timestamp.unwrap_or(0);
+        for entity in feed.entity.into_iter().filter(|e| e.is_deleted != Some(true)) {
+            if let Some(vehicle) = entity.vehicle {
+                let timestamp = vehicle.timestamp.unwrap_or(feed_timestamp) as i64;
+                let trip = vehicle.trip.as_ref().filter(|trip| service_day.contains(trip, timestamp));
+                let trip_id = trip.and_then(|t| t.trip_id.clone());
+                let vehicle_id = vehicle.vehicle.as_ref().and_then(|v| v.id.clone()).or(trip_id.clone());
+                if let (Some(trip_id), Some(vehicle_id), Some(position), Some(time)) = (trip_id, vehicle_id, vehicle.position, service_day.time_of_day(timestamp)) {
+                    self.positions.entry(vehicle_id).or_default().push(VehicleObservation {
+                        trip_id,
+                        time,
+                        lat: position.latitude as f64,
+                        lon: position.longitude as f64,
+                    });
+                }
+            }
+            if let Some(trip_update) = entity.trip_update {
+                let Some(trip_id) = trip_update.trip.trip_id.clone() else {
+                    continue;
+                };
+                let timestamp = trip_update.timestamp.unwrap_or(feed_timestamp);
+                if !service_day.contains(&trip_update.trip, timestamp as i64) {
+                    continue;
+                }
+                if self.trip_delays.get(&trip_id).is_some_and(|d| d.timestamp > timestamp) {
+                    continue;
+                }
+                let stops = trip_update.stop_time_update.iter()
+                    .filter(|update| update.stop_sequence.is_some() || update.stop_id.is_some())
+                    .map(|update| StopDelay {
+                        stop_sequence: update.stop_sequence,
+                        stop_id: update.stop_id.clone(),
+                        arrival: stop_time(&update.arrival, service_day),
+                        departure: stop_time(&update.departure, service_day),
+                    })
+                    .collect();
+                self.trip_delays.insert(trip_id, TripDelay { timestamp, delay: trip_update.delay.unwrap_or(0) as i64, stops });
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the latest update of a trip to its stop times. The delay of a stop holds for the following stops until the next
+    /// stop with an update, stops before the first update keep the delay of the trip. Updates are matched to the stops of the
+    /// trip in the order of the trip by their stop sequence or, without one, by their stop id. Updates that match none of the
+    /// remaining stops are skipped. Returns false if there is no update for the trip.
+    pub fn apply_trip_updates(&self, trip: &mut TripSchedule) -> Result<bool, Box<dyn std::error::Error>> {
+        let Some(trip_delay) = self.trip_delays.get(&trip.trip_id) else {
+            return Ok(false);
+        };
+        let mut updates: HashMap<usize, &StopDelay> = HashMap::new();
+        let mut next_stop = 0;
+        for update in &trip_delay.stops {
+            let position = trip.stops[next_stop..].iter().position(|stop| match update.stop_sequence {
+                Some(stop_sequence) => stop.stop_sequence == stop_sequence as u64,
+                None => update.stop_id.as_ref() == Some(&stop.stop_id),
+            });
+            match position {
+                Some(position) => {
+                    updates.insert(next_stop + position, update);
+                    next_stop += position + 1;
+                }
+                None => println!("Skipping the update of trip {} for stop {:?} with sequence {:?}, it matches none of the following stops", trip.trip_id, update.stop_id, update.stop_sequence),
+            }
+        }
+
+        let mut delay = trip_delay.delay;
+        for (i, stop) in trip.stops.iter_mut().enumerate() {
+            let arrival = parse_duration(&stop.arrival_time)?;
+            let departure = parse_duration(&stop.departure_time)?;
+            let delayed = |time: Duration, delay: i64| Duration::from_secs((time.as_secs() as i64 + delay).max(0) as u64);
+            let (mut new_arrival, mut new_departure) = (delayed(arrival, delay), delayed(departure, delay));
+            if let Some(update) = updates.get(&i) {
+                match update.arrival {
+                    Some(StopTime::Time(time)) => new_arrival = time,
+                    Some(StopTime::Delay(arrival_delay)) => new_arrival = delayed(arrival, arrival_delay),
+                    None => {}
+                }
+                new_departure = match update.departure {
+                    Some(StopTime::Time(time)) => time,
+                    Some(StopTime::Delay(departure_delay)) => delayed(departure, departure_delay),
+                    None => new_arrival + departure.saturating_sub(arrival),
+                };
+                delay = new_departure.as_secs() as i64 - departure.as_secs() as i64;
+            }
+            stop.arrival_time = duration_to_string(new_arrival);
+            stop.departure_time = duration_to_string(new_departure.max(new_arrival));
+        }
+        Ok(true)
+    }
+
+    /// Create a block for every vehicle whose positions are observed while serving one of the given trips. The trip to route
+    /// map selects the trips and gives the route of a vehicle, which is the route of its first observed trip. Only observations
+    /// inside the time window are kept.
+    pub fn vehicle_blocks(&self, trip_routes: &HashMap<String, String>, start_time: Duration, end_time: Duration) -> Vec<PartialBlock> {
+        let mut blocks = vec![];
+        for (vehicle_id, observations) in &self.positions {
+            let observations: Vec<&VehicleObservation> = observations.iter()
+                .filter(|o| trip_routes.contains_key(&o.trip_id) && o.time >= start_time && o.time <= end_time)
+                .collect();
+            let Some(first) = observations.first() else {
+                continue;
+            };
+            let shape_points = observations.iter().enumerate().map(|(i, o)| ShapePoint {
+                shape_id: vehicle_id.clone(),
+                shape_pt_lat: o.lat,
+                shape_pt_lon: o.lon,
+                shape_pt_sequence: i as u64,
+                shape_dist_traveled: None,
+                time: Some(o.time),
+                synthetic: false,
+            }).collect();
+            blocks.push(PartialBlock {
+                block_id: vehicle_id.clone(),
+                route_id: trip_routes[&first.trip_id].clone(),
+                stops: vec![],
+                shape_points,
+            });
+        }
+        println!("Replaying the observed positions of {} vehicles", blocks.len());
+        blocks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_feed() {
+        //08:00:00 in UTC+1 on 2024-01-08
+        let timestamp = 1704697200u64;
+        let service_day = ServiceDay::new("20240108", "Europe/Berlin").unwrap();
+        let entity = |id: &str, trip_update: Option<TripUpdate>, vehicle: Option<VehiclePosition>| FeedEntity { id: id.to_string(), is_deleted: None, trip_update, vehicle };
+        let trip = TripDescriptor { trip_id: Some("t1".to_string()), start_date: None, route_id: None };
+        let feed = FeedMessage {
+            header: FeedHeader { gtfs_realtime_version: "2.0".to_string(), timestamp: Some(timestamp) },
+            entity: vec![
+                entity("1", None, Some(VehiclePosition {
+                    trip: Some(trip.clone()),
+                    position: Some(Position { latitude: 52.5, longitude: 13.4 }),
+                    timestamp: Some(timestamp + 30),
+                    vehicle: Some(VehicleDescriptor { id: Some("v1".to_string()), label: None }),
+                })),
+                entity("2", Some(TripUpdate {
+                    trip,
+                    stop_time_update: vec![StopTimeUpdate { stop_sequence: None, arrival: Some(StopTimeEvent { delay: Some(120), time: None }), departure: None, stop_id: Some("B".to_string()) }],
+                    vehicle: None,
+                    timestamp: None,
+                    delay: None,
+                }), None),
+            ],
+        };
+        let mut archive = RealtimeArchive::default();
+        archive.add_feed(&feed.encode_to_vec(), &service_day).unwrap();
+
+        let blocks = archive.vehicle_blocks(&HashMap::from([("t1".to_string(), "S41".to_string())]), Duration::from_secs(8 * 3600), Duration::from_secs(9 * 3600));
+        assert_eq!(blocks.len(), 1);
+        assert_eq!((blocks[0].block_id.as_str(), blocks[0].route_id.as_str()), ("v1", "S41"));
+        assert_eq!(blocks[0].shape_points[0].time, Some(Duration::from_secs(8 * 3600 + 30)));
+
+        let mut schedule = TripSchedule::test("t1", &[("A", 13.4, "08:00:00"), ("B", 13.4, "08:05:00"), ("C", 13.4, "08:10:00")]);
+        assert!(archive.apply_trip_updates(&mut schedule).unwrap());
+        let times: Vec<&str> = schedule.stops.iter().map(|s| s.arrival_time.as_str()).collect();
+        assert_eq!(times, vec!["08:00:00", "08:07:00", "08:12:00"]);
+    }
+
+    #[test]
+    fn test_service_day() {
+        //08:00:00 in UTC+2 on 2024-07-08
+        let timestamp = 1720418400u64;
+        let service_day = ServiceDay::new("20240708", "Europe/Berlin").unwrap();
+        let position = |trip_id: &str, start_date: Option<&str>, timestamp: u64| FeedEntity {
+            id: trip_id.to_string(),
+            is_deleted: None,
+            trip_update: None,
+            vehicle: Some(VehiclePosition {
+                trip: Some(TripDescriptor { trip_id: Some(trip_id.to_string()), start_date: start_date.map(str::to_string), route_id: None }),
+                position: Some(Position { latitude: 52.5, longitude: 13.4 }),
+                timestamp: Some(timestamp),
+                vehicle: Some(VehicleDescriptor { id: Some("v1".to_string()), label: None }),
+            }),
+        };
+        let feed = FeedMessage {
+            header: FeedHeader { gtfs_realtime_version: "2.0".to_string(), timestamp: None },
+            entity: vec![
+                position("day", None, timestamp),
+                //the same trip on the next day
+                position("day", None, timestamp + 24 * 3600),
+                //a trip of the service day after midnight
+                position("night", Some("20240708"), timestamp + 16 * 3600 + 1800),
+                position("night", Some("20240707"), timestamp + 1800),
+            ],
+        };
+        let mut archive = RealtimeArchive::default();
+        archive.add_feed(&feed.encode_to_vec(), &service_day).unwrap();
+        let times: Vec<(&str, u64)> = archive.positions["v1"].iter().map(|o| (o.trip_id.as_str(), o.time.as_secs())).collect();
+        assert_eq!(times, vec![("day", 8 * 3600), ("night", 24 * 3600 + 1800)]);
+    }
+
+    #[test]
+    fn test_match_stop_time_updates() {
+        let update = |stop_sequence: Option<u32>, stop_id: Option<&str>, delay: i64| StopDelay {
+            stop_sequence,
+            stop_id: stop_id.map(str::to_string),
+            arrival: Some(StopTime::Delay(delay)),
+            departure: None,
+        };
+        let stops = vec![
+            //a stop that is not part of the trip is skipped instead of blocking the following updates
+            update(None, Some("X"), 600),
+            //an update with a stop sequence but without a stop id
+            update(Some(2), None, 60),
+            //the stop sequence tells the two visits of the loop apart
+            update(Some(4), Some("A"), 300),
+        ];
+        let archive = RealtimeArchive {
+            positions: BTreeMap::new(),
+            trip_delays: HashMap::from([("loop".to_string(), TripDelay { timestamp: 0, delay: 0, stops })]),
+        };
+        let mut schedule = TripSchedule::test("loop", &[("A", 0.0, "08:00:00"), ("B", 0.01, "08:05:00"), ("C", 0.02, "08:10:00"), ("A", 0.0, "08:15:00")]);
+        assert!(archive.apply_trip_updates(&mut schedule).unwrap());
+        let times: Vec<&str> = schedule.stops.iter().map(|s| s.arrival_time.as_str()).collect();
+        assert_eq!(times, vec!["08:00:00", "08:06:00", "08:11:00", "08:20:00"]);
+    }
+}
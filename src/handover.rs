@@ -0,0 +1,57 @@
+use std::time::Duration;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Duration of the interruption between removing the old and adding the new link of a break-before-make handover
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InterruptionGap {
+    Fixed(Duration),
+    /// Drawn uniformly from the range [min, max] for every handover
+    Uniform { min: Duration, max: Duration },
+}
+
+/// Order and timing of the remove and add event of a handover
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HandoverMode {
+    /// Remove and add are part of the same topology update
+    Instant,
+    /// The new link is added first, the old link is removed after the overlap
+    MakeBeforeBreak { overlap: Duration },
+    /// The old link is removed first, the new link is added after the interruption gap
+    BreakBeforeMake { gap: InterruptionGap },
+}
+
+/// Draws the delay between the two events of each handover. Random gaps are reproducible for a given seed.
+pub struct HandoverTiming {
+    mode: HandoverMode,
+    rng: StdRng,
+}
+
+impl HandoverTiming {
+    pub fn new(mode: HandoverMode, seed: u64) -> Self {
+        HandoverTiming {
+            mode,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// True if the add event of a handover is emitted before the remove event
+    pub fn adds_first(&self) -> bool {
+        matches!(self.mode, HandoverMode::MakeBeforeBreak { .. })
+    }
+
+    /// The time between the first and the second event of the next handover
+    pub fn next_delay(&mut self) -> Duration {
+        match self.mode {
+            HandoverMode::Instant => Duration::ZERO,
+            HandoverMode::MakeBeforeBreak { overlap } => overlap,
+            HandoverMode::BreakBeforeMake { gap: InterruptionGap::Fixed(gap) } => gap,
+            HandoverMode::BreakBeforeMake { gap: InterruptionGap::Uniform { min, max } } => {
+                if max <= min {
+                    return min;
+                }
+                Duration::from_millis(self.rng.gen_range(min.as_millis() as u64..=max.as_millis() as u64))
+            }
+        }
+    }
+}
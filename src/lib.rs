@@ -3,6 +3,26 @@ mod geo_utils;
 pub mod gtfs;
 pub mod cell_data;
 pub mod nes_simulation;
+pub mod manifest;
+pub mod sites;
+pub mod tower_selection;
+pub mod backhaul;
+pub mod link_model;
+pub mod handover;
+pub mod time_mapping;
+pub mod event_rate;
+pub mod attachment_intervals;
+pub mod connection_log;
+pub mod topology_state;
+pub mod map_matching;
+pub mod movement;
+pub mod shape_synthesis;
+pub mod block_synthesis;
+pub mod frequencies;
+pub mod bulk_loader;
+pub mod perturbation;
+pub mod gtfs_realtime;
+pub mod scenarios;
 
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
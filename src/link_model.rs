@@ -0,0 +1,180 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use serde::{Deserialize, Serialize};
+use crate::backhaul::DistanceLatencyModel;
+use crate::cell_data::{CellGlobalId, Radio};
+use crate::geo_utils;
+use crate::nes_simulation::{FixedTopology, LinkAttributes, SimulatedReconnects, TopologyLink};
+
+/// Properties of the radio link between a vehicle and a cell of a given radio type
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct RadioLinkProfile {
+    pub latency_in_ms: f64,
+    pub bandwidth_in_mbps: f64,
+    pub packet_loss: f64,
+}
+
+impl RadioLinkProfile {
+    /// Typical values for the user plane of each radio access technology
+    pub fn default_for(radio: Radio) -> Self {
+        let (latency_in_ms, bandwidth_in_mbps, packet_loss) = match radio {
+            Radio::GSM => (150.0, 0.1, 0.01),
+            Radio::CDMA => (100.0, 1.0, 0.01),
+            Radio::UMTS => (60.0, 2.0, 0.005),
+            Radio::LTE => (30.0, 20.0, 0.001),
+            Radio::NR => (10.0, 100.0, 0.0001),
+        };
+        RadioLinkProfile { latency_in_ms, bandwidth_in_mbps, packet_loss }
+    }
+}
+
+/// Properties of a wired link. The latency is estimated from the length of the link.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct WiredLinkProfile {
+    pub latency: DistanceLatencyModel,
+    pub bandwidth_in_mbps: f64,
+    pub packet_loss: f64,
+}
+
+impl WiredLinkProfile {
+    fn attributes(&self, distance_in_meters: f64) -> LinkAttributes {
+        LinkAttributes {
+            latency_in_ms: Some(self.latency.latency_in_ms(distance_in_meters)),
+            bandwidth_in_mbps: Some(self.bandwidth_in_mbps),
+            packet_loss: Some(self.packet_loss),
+        }
+    }
+}
+
+/// Derives latency, bandwidth and packet loss of the links of a topology. Links from edge servers to towers use the edge profile,
+/// all other fixed links the backhaul profile. Links of mobile nodes use the radio profile of the radio type of their parent.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct LinkModel {
+    pub backhaul: WiredLinkProfile,
+    pub edge: WiredLinkProfile,
+    pub radio: HashMap<Radio, RadioLinkProfile>,
+}
+
+impl Default for LinkModel {
+    fn default() -> Self {
+        let fiber = DistanceLatencyModel { base_latency_in_ms: 0.1, latency_per_km_in_ms: 0.005 };
+        LinkModel {
+            backhaul: WiredLinkProfile { latency: fiber, bandwidth_in_mbps: 10000.0, packet_loss: 0.0 },
+            edge: WiredLinkProfile { latency: fiber, bandwidth_in_mbps: 1000.0, packet_loss: 0.0 },
+            radio: [Radio::GSM, Radio::CDMA, Radio::UMTS, Radio::LTE, Radio::NR].into_iter().map(|r| (r, RadioLinkProfile::default_for(r))).collect(),
+        }
+    }
+}
+
+impl LinkModel {
+    pub fn read_from_file(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let json_string = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&json_string)?)
+    }
+
+    /// Set the attributes of every parent-child edge of the topology. Attributes that were already set, e.g. the estimated latency of a backhaul link, are kept.
+    pub fn annotate_topology(&self, topology: &mut FixedTopology, edge_servers: &HashSet<u64>) {
+        let mut links: HashMap<(u64, u64), TopologyLink> = topology.links.drain(..).map(|l| ((l.parent_id, l.child_id), l)).collect();
+        for (parent_id, children) in &topology.children {
+            for child_id in children {
+                let parent = &topology.nodes[parent_id];
+                let child = &topology.nodes[child_id];
+                let distance = geo_utils::vincenty_dist_between_coordinates((parent[1], parent[0]), (child[1], child[0]));
+                let profile = if edge_servers.contains(parent_id) { &self.edge } else { &self.backhaul };
+                let derived = profile.attributes(distance);
+                let link = links.entry((*parent_id, *child_id)).or_insert(TopologyLink {
+                    parent_id: *parent_id,
+                    child_id: *child_id,
+                    attributes: LinkAttributes::default(),
+                });
+                link.attributes.latency_in_ms = link.attributes.latency_in_ms.or(derived.latency_in_ms);
+                link.attributes.bandwidth_in_mbps = link.attributes.bandwidth_in_mbps.or(derived.bandwidth_in_mbps);
+                link.attributes.packet_loss = link.attributes.packet_loss.or(derived.packet_loss);
+            }
+        }
+        let mut links: Vec<TopologyLink> = links.into_values().collect();
+        links.sort_by_key(|l| (l.parent_id, l.child_id));
+        topology.links = links;
+    }
+
+    /// Set the attributes of the links between the mobile nodes and their parents
+    pub fn annotate_reconnects(&self, reconnects: &mut SimulatedReconnects, radio_of_node: &HashMap<u64, Radio>) {
+        reconnects.set_link_attributes(|parent_id, _| {
+            match radio_of_node.get(&parent_id).and_then(|radio| self.radio.get(radio)) {
+                Some(profile) => LinkAttributes {
+                    latency_in_ms: Some(profile.latency_in_ms),
+                    bandwidth_in_mbps: Some(profile.bandwidth_in_mbps),
+                    packet_loss: Some(profile.packet_loss),
+                },
+                None => LinkAttributes::default(),
+            }
+        });
+    }
+}
+
+/// The radio type offered by each fog node. Nodes representing several cells offer the radio type of their cells with the latest
+/// generation, GSM before CDMA if a node has cells of both.
+pub fn radio_of_nodes(cell_id_to_node_id: &HashMap<CellGlobalId, u64>) -> HashMap<u64, Radio> {
+    let mut radio_of_node: HashMap<u64, Radio> = HashMap::new();
+    for (cell_id, node_id) in cell_id_to_node_id {
+        let radio = radio_of_node.entry(*node_id).or_insert(cell_id.radio);
+        //the order of the variants only breaks ties so that the result does not depend on the iteration order
+        if (cell_id.radio.generation(), cell_id.radio) > (radio.generation(), *radio) {
+            *radio = cell_id.radio;
+        }
+    }
+    radio_of_node
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_radio_of_nodes() {
+        let cell = |radio, cell_id| CellGlobalId { radio, mcc: 262, mnc: 2, area_code: 1, cell_id };
+        let cell_id_to_node_id = HashMap::from([
+            (cell(Radio::CDMA, 1), 2), (cell(Radio::UMTS, 2), 2),
+            (cell(Radio::LTE, 3), 3), (cell(Radio::NR, 4), 3),
+            (cell(Radio::GSM, 5), 4), (cell(Radio::CDMA, 6), 4),
+        ]);
+        let radio_of_node = radio_of_nodes(&cell_id_to_node_id);
+        assert_eq!(radio_of_node, HashMap::from([(2, Radio::UMTS), (3, Radio::NR), (4, Radio::CDMA)]));
+    }
+
+    #[test]
+    fn test_link_model_overrides() {
+        let default = LinkModel::default();
+        assert_eq!(default.radio.len(), 5);
+        assert_eq!(default.radio[&Radio::LTE], RadioLinkProfile::default_for(Radio::LTE));
+        assert!(default.radio[&Radio::NR].latency_in_ms < default.radio[&Radio::GSM].latency_in_ms);
+
+        //profiles missing in the file keep their defaults, a radio map replaces the default radio profiles
+        let model: LinkModel = serde_json::from_str(r#"{"edge": {"latency": {"base_latency_in_ms": 2.0, "latency_per_km_in_ms": 0.0}, "bandwidth_in_mbps": 100.0, "packet_loss": 0.01},
+            "radio": {"LTE": {"latency_in_ms": 50.0, "bandwidth_in_mbps": 5.0, "packet_loss": 0.02}}}"#).unwrap();
+        assert_eq!(model.backhaul, default.backhaul);
+        assert_eq!(model.edge.bandwidth_in_mbps, 100.0);
+        assert_eq!(model.radio.len(), 1);
+
+        //edge server 1 with tower 2, tower 2 with tower 3
+        let mut topology = FixedTopology {
+            nodes: HashMap::from([(1, vec![13.0, 52.0]), (2, vec![13.0, 52.0]), (3, vec![13.0, 52.0])]),
+            slots: HashMap::new(),
+            children: HashMap::from([(1, vec![2]), (2, vec![3])]),
+            links: vec![],
+            root: None,
+        };
+        model.annotate_topology(&mut topology, &HashSet::from([1]));
+        let latencies: Vec<(u64, Option<f64>, Option<f64>)> = topology.links.iter().map(|l| (l.parent_id, l.attributes.latency_in_ms, l.attributes.bandwidth_in_mbps)).collect();
+        assert_eq!(latencies, vec![(1, Some(2.0), Some(100.0)), (2, Some(0.1), Some(10000.0))]);
+
+        let mut reconnects: SimulatedReconnects = serde_json::from_str(r#"{"initial_parents": [[2, 10], [3, 11]], "topology_updates": []}"#).unwrap();
+        model.annotate_reconnects(&mut reconnects, &HashMap::from([(2, Radio::LTE), (3, Radio::NR)]));
+        let json = serde_json::to_value(&reconnects).unwrap();
+        let links = json["initial_links"].as_array().unwrap();
+        assert_eq!(links[0]["latencyInMs"], 50.0);
+        //no profile for NR in the overridden model
+        assert!(links[1].get("latencyInMs").is_none());
+    }
+}
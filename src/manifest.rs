@@ -0,0 +1,268 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use serde::{Deserialize, Serialize};
+use crate::cell_data::{CellGlobalId, RadioCell};
+use crate::gtfs::{PartialBlock, Stop};
+use crate::nes_simulation::FixedTopology;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeRole {
+    Coordinator,
+    Fog,
+    #[serde(rename = "edge_server")]
+    EdgeServer,
+    Aggregation,
+    Mobile,
+}
+
+impl NodeRole {
+    fn as_str(&self) -> &'static str {
+        match self {
+            NodeRole::Coordinator => "coordinator",
+            NodeRole::Fog => "fog",
+            NodeRole::EdgeServer => "edge_server",
+            NodeRole::Aggregation => "aggregation",
+            NodeRole::Mobile => "mobile",
+        }
+    }
+}
+
+/// An OpenCelliD entry a fog node was created from
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CellEntry {
+    #[serde(flatten)]
+    pub id: CellGlobalId,
+    pub range: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StopEntry {
+    pub stop_id: String,
+    pub stop_name: String,
+    pub arrival_time: String,
+    pub departure_time: String,
+}
+
+/// The GTFS block a mobile node was created from
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VehicleEntry {
+    pub block_id: String,
+    pub route_short_name: String,
+    pub trip_ids: Vec<String>,
+    pub first_stop: Option<StopEntry>,
+    pub last_stop: Option<StopEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NodeManifestEntry {
+    pub node_id: u64,
+    pub role: NodeRole,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    /// The nodes listing this node as child in fixed_topology.json, or the initial parent of a mobile node
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub parents: Vec<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub cells: Vec<CellEntry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vehicle: Option<VehicleEntry>,
+}
+
+/// Maps every node id of a generated scenario back to the GTFS and OpenCelliD entities it represents
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NodeManifest {
+    pub nodes: Vec<NodeManifestEntry>,
+}
+
+impl From<&Stop> for StopEntry {
+    fn from(stop: &Stop) -> Self {
+        StopEntry {
+            stop_id: stop.stop_id.clone(),
+            stop_name: stop.stop_name.clone(),
+            arrival_time: stop.arrival_time.clone(),
+            departure_time: stop.departure_time.clone(),
+        }
+    }
+}
+
+// the nodes of the topology having the node as child, sorted by id
+fn parents_in_topology(topology: &FixedTopology, node_id: u64) -> Vec<u64> {
+    let mut parents: Vec<u64> = topology.children.iter().filter(|(_, children)| children.contains(&node_id)).map(|(parent, _)| *parent).collect();
+    parents.sort();
+    parents
+}
+
+impl NodeManifest {
+    pub fn new(coordinator_id: u64, topology: &FixedTopology, radio_cells: &HashMap<CellGlobalId, RadioCell>, cell_id_to_node_id: &HashMap<CellGlobalId, u64>, blocks: &[PartialBlock], trip_to_node: &HashMap<String, u64>, initial_parents: &[(u64, u64)]) -> Self {
+        //the coordinator only has a location if it is part of the topology
+        let coordinator_location = topology.nodes.get(&coordinator_id);
+        let mut nodes = vec![NodeManifestEntry {
+            node_id: coordinator_id,
+            role: NodeRole::Coordinator,
+            lat: coordinator_location.map(|l| l[1]),
+            lon: coordinator_location.map(|l| l[0]),
+            parents: vec![],
+            cells: vec![],
+            vehicle: None,
+        }];
+
+        //a fog node represents several cells if they were aggregated into a site
+        let mut node_id_to_cells: BTreeMap<u64, Vec<CellEntry>> = BTreeMap::new();
+        for (cell_id, node_id) in cell_id_to_node_id {
+            let cell = radio_cells.get(cell_id).expect("Fog node without a corresponding radio cell");
+            node_id_to_cells.entry(*node_id).or_default().push(CellEntry {
+                id: cell.id,
+                range: cell.range,
+            });
+        }
+        for (node_id, mut cells) in node_id_to_cells {
+            cells.sort_by_key(|c| c.id);
+            let location = topology.nodes.get(&node_id).expect("Fog node missing in topology");
+            nodes.push(NodeManifestEntry {
+                node_id,
+                role: NodeRole::Fog,
+                lat: Some(location[1]),
+                lon: Some(location[0]),
+                parents: parents_in_topology(topology, node_id),
+                cells,
+                vehicle: None,
+            });
+        }
+
+        for block in blocks {
+            let Some(node_id) = trip_to_node.get(&block.block_id) else {
+                continue;
+            };
+            //the position of a mobile node is the first point of its trajectory inside the time window
+            let first_point = block.shape_points.iter().min_by_key(|p| p.time);
+            let mut trip_ids: Vec<String> = vec![];
+            for stop in &block.stops {
+                if !trip_ids.contains(&stop.trip_id) {
+                    trip_ids.push(stop.trip_id.clone());
+                }
+            }
+            nodes.push(NodeManifestEntry {
+                node_id: *node_id,
+                role: NodeRole::Mobile,
+                lat: first_point.map(|p| p.shape_pt_lat),
+                lon: first_point.map(|p| p.shape_pt_lon),
+                parents: initial_parents.iter().filter(|(_, child_id)| child_id == node_id).map(|(parent_id, _)| *parent_id).collect(),
+                cells: vec![],
+                vehicle: Some(VehicleEntry {
+                    block_id: block.block_id.clone(),
+                    route_short_name: block.route_id.clone(),
+                    trip_ids,
+                    first_stop: block.stops.first().map(StopEntry::from),
+                    last_stop: block.stops.last().map(StopEntry::from),
+                }),
+            });
+        }
+
+        nodes.sort_by_key(|n| n.node_id);
+        NodeManifest { nodes }
+    }
+
+    /// Add topology nodes that do not correspond to a radio cell or a vehicle, e.g. edge servers or aggregation points
+    pub fn add_nodes(&mut self, topology: &FixedTopology, node_ids: &[u64], role: NodeRole) {
+        for node_id in node_ids {
+            let location = topology.nodes.get(node_id).expect("Node missing in topology");
+            self.nodes.push(NodeManifestEntry {
+                node_id: *node_id,
+                role: role.clone(),
+                lat: Some(location[1]),
+                lon: Some(location[0]),
+                parents: parents_in_topology(topology, *node_id),
+                cells: vec![],
+                vehicle: None,
+            });
+        }
+        self.nodes.sort_by_key(|n| n.node_id);
+    }
+
+    pub fn write_to_file(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let json_string = serde_json::to_string_pretty(self)?;
+        Ok(fs::write(path, json_string)?)
+    }
+
+    /// Write the manifest as a flat csv table with one row per node. Cell columns of fog nodes representing several cells are separated by ';'
+    pub fn write_to_csv(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.write_record(["node_id", "role", "lat", "lon", "parents", "radio", "mcc", "mnc", "area_code", "cell_id", "range", "block_id", "route_short_name", "trip_ids", "first_stop_id", "first_stop_name", "last_stop_id", "last_stop_name"])?;
+        for node in &self.nodes {
+            let optional = |value: Option<String>| value.unwrap_or_default();
+            let cells = |value: fn(&CellEntry) -> String| node.cells.iter().map(value).collect::<Vec<_>>().join(";");
+            let vehicle = node.vehicle.as_ref();
+            let first_stop = vehicle.and_then(|v| v.first_stop.as_ref());
+            let last_stop = vehicle.and_then(|v| v.last_stop.as_ref());
+            writer.write_record([
+                node.node_id.to_string(),
+                node.role.as_str().to_string(),
+                optional(node.lat.map(|x| x.to_string())),
+                optional(node.lon.map(|x| x.to_string())),
+                node.parents.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(";"),
+                cells(|c| c.id.radio.to_string()),
+                cells(|c| c.id.mcc.to_string()),
+                cells(|c| c.id.mnc.to_string()),
+                cells(|c| c.id.area_code.to_string()),
+                cells(|c| c.id.cell_id.to_string()),
+                cells(|c| c.range.to_string()),
+                optional(vehicle.map(|v| v.block_id.clone())),
+                optional(vehicle.map(|v| v.route_short_name.clone())),
+                optional(vehicle.map(|v| v.trip_ids.join(";"))),
+                optional(first_stop.map(|s| s.stop_id.clone())),
+                optional(first_stop.map(|s| s.stop_name.clone())),
+                optional(last_stop.map(|s| s.stop_id.clone())),
+                optional(last_stop.map(|s| s.stop_name.clone())),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell_data::Radio;
+
+    #[test]
+    fn test_manifest_columns() {
+        let cell = |cell_id| CellGlobalId { radio: Radio::LTE, mcc: 262, mnc: 2, area_code: 1, cell_id };
+        //coordinator 1 above fog node 2, fog node 3 is attached to edge server 4
+        let topology = FixedTopology {
+            nodes: HashMap::from([(1, vec![13.0, 52.0]), (2, vec![13.1, 52.1]), (3, vec![13.2, 52.2]), (4, vec![13.2, 52.2])]),
+            slots: HashMap::new(),
+            children: HashMap::from([(1, vec![2, 4]), (2, vec![]), (3, vec![]), (4, vec![3])]),
+            links: vec![],
+            root: Some(1),
+        };
+        let radio_cells = HashMap::from([10, 11].map(|cell_id| (cell(cell_id), RadioCell { lat: 52.0, lon: 13.0, id: cell(cell_id), range: 1000.0 })));
+        let cell_id_to_node_id = HashMap::from([(cell(10), 2), (cell(11), 3)]);
+        let stops = vec![Stop::test("t1", "A", (52.1, 13.1), "08:00:00", "08:00:00"), Stop::test("t2", "B", (52.2, 13.2), "08:10:00", "08:10:00")];
+        let blocks = vec![
+            PartialBlock { block_id: "b1".to_string(), route_id: "S41".to_string(), stops, shape_points: vec![] },
+            //blocks without a mobile node are not listed
+            PartialBlock { block_id: "b2".to_string(), route_id: "S42".to_string(), stops: vec![], shape_points: vec![] },
+        ];
+        let trip_to_node = HashMap::from([("b1".to_string(), 5)]);
+        let mut manifest = NodeManifest::new(1, &topology, &radio_cells, &cell_id_to_node_id, &blocks, &trip_to_node, &[(2, 5)]);
+        manifest.add_nodes(&topology, &[4], NodeRole::EdgeServer);
+
+        let path = std::env::temp_dir().join("test_manifest_columns.csv");
+        manifest.write_to_csv(path.to_str().unwrap()).unwrap();
+        let mut reader = csv::Reader::from_path(&path).unwrap();
+        let headers = reader.headers().unwrap().clone();
+        let rows: Vec<HashMap<String, String>> = reader.records()
+            .map(|r| headers.iter().zip(r.unwrap().iter()).map(|(h, v)| (h.to_string(), v.to_string())).collect())
+            .collect();
+        std::fs::remove_file(path).unwrap();
+        let column = |name: &str| rows.iter().map(|r| r[name].as_str()).collect::<Vec<_>>();
+        assert_eq!(column("node_id"), vec!["1", "2", "3", "4", "5"]);
+        assert_eq!(column("role"), vec!["coordinator", "fog", "fog", "edge_server", "mobile"]);
+        assert_eq!(column("parents"), vec!["", "1", "4", "1", "2"]);
+        assert_eq!(column("cell_id"), vec!["", "10", "11", "", ""]);
+        assert_eq!(column("route_short_name"), vec!["", "", "", "", "S41"]);
+        assert_eq!((rows[4]["trip_ids"].as_str(), rows[4]["first_stop_id"].as_str(), rows[4]["last_stop_id"].as_str()), ("t1;t2", "A", "B"));
+    }
+}
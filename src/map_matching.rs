@@ -0,0 +1,128 @@
+use crate::geo_utils::vincenty_dist_between_coordinates;
+use crate::gtfs::{ShapePoint, Stop};
+
+/// The shape point a stop was matched to and the distance between them in meters
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StopMatch {
+    pub shape_index: usize,
+    pub distance: f64,
+}
+
+/// Match every stop of a trip to a point of its shape, keeping the order of the stops along the shape. Shape points are expected
+/// to be sorted by their sequence number and stops by their stop sequence. If all stops and shape points carry a
+/// `shape_dist_traveled` it is used directly, otherwise the sum of the distances between the stops and their matched points is
+/// minimized. Keeping the order makes the match unambiguous on loops and on shapes that run back and forth along the same track.
+pub fn match_stops_to_shape(stops: &[Stop], shape_points: &[ShapePoint]) -> Vec<StopMatch> {
+    if stops.is_empty() || shape_points.is_empty() {
+        return vec![];
+    }
+    let has_distance_traveled = stops.iter().all(|s| s.shape_dist_traveled.is_some())
+        && shape_points.iter().all(|p| p.shape_dist_traveled.is_some());
+    let shape_indices = if has_distance_traveled {
+        match_by_distance_traveled(stops, shape_points)
+    } else {
+        match_by_dynamic_programming(stops, shape_points)
+    };
+    shape_indices.into_iter().zip(stops).map(|(shape_index, stop)| StopMatch {
+        shape_index,
+        distance: distance(stop, &shape_points[shape_index]),
+    }).collect()
+}
+
+fn distance(stop: &Stop, shape_point: &ShapePoint) -> f64 {
+    vincenty_dist_between_coordinates((stop.lat, stop.lon), (shape_point.shape_pt_lat, shape_point.shape_pt_lon))
+}
+
+// pick the shape point whose distance traveled is closest to the one of the stop without going back along the shape
+fn match_by_distance_traveled(stops: &[Stop], shape_points: &[ShapePoint]) -> Vec<usize> {
+    let mut indices = vec![];
+    let mut index = 0;
+    for stop in stops {
+        let stop_distance = stop.shape_dist_traveled.unwrap();
+        while index + 1 < shape_points.len() && shape_points[index + 1].shape_dist_traveled.unwrap() <= stop_distance {
+            index += 1;
+        }
+        if index + 1 < shape_points.len() {
+            let before = stop_distance - shape_points[index].shape_dist_traveled.unwrap();
+            let after = shape_points[index + 1].shape_dist_traveled.unwrap() - stop_distance;
+            if after < before {
+                index += 1;
+            }
+        }
+        indices.push(index);
+    }
+    indices
+}
+
+// cost[j] is the smallest sum of distances of the stops so far if the current stop is matched to shape point j
+fn match_by_dynamic_programming(stops: &[Stop], shape_points: &[ShapePoint]) -> Vec<usize> {
+    let mut cost: Vec<f64> = shape_points.iter().map(|p| distance(&stops[0], p)).collect();
+    //predecessors[i][j] is the point of stop i - 1 on the cheapest path matching stop i to point j
+    let mut predecessors = vec![vec![0; shape_points.len()]];
+    for stop in &stops[1..] {
+        let mut best_previous = 0;
+        let mut next_cost = vec![0.0; shape_points.len()];
+        let mut stop_predecessors = vec![0; shape_points.len()];
+        for (j, shape_point) in shape_points.iter().enumerate() {
+            if cost[j] < cost[best_previous] {
+                best_previous = j;
+            }
+            next_cost[j] = cost[best_previous] + distance(stop, shape_point);
+            stop_predecessors[j] = best_previous;
+        }
+        cost = next_cost;
+        predecessors.push(stop_predecessors);
+    }
+
+    let mut index = (0..shape_points.len()).min_by(|a, b| cost[*a].total_cmp(&cost[*b])).unwrap();
+    let mut indices = vec![index];
+    for stop_predecessors in predecessors[1..].iter().rev() {
+        index = stop_predecessors[index];
+        indices.push(index);
+    }
+    indices.reverse();
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stop(lat: f64, lon: f64, shape_dist_traveled: Option<f64>) -> Stop {
+        Stop { shape_dist_traveled, ..Stop::test("trip", &format!("{},{}", lat, lon), (lat, lon), "08:00:00", "08:00:00") }
+    }
+
+    fn shape(coordinates: &[(f64, f64)]) -> Vec<ShapePoint> {
+        coordinates.iter().enumerate().map(|(i, location)| ShapePoint {
+            shape_dist_traveled: Some(i as f64 * 100.0),
+            ..ShapePoint::test("shape", i as u64, *location)
+        }).collect()
+    }
+
+    #[test]
+    fn test_match_loop() {
+        //a square loop that starts and ends at the same location
+        let shape_points = shape(&[(52.50, 13.40), (52.50, 13.41), (52.51, 13.41), (52.51, 13.40), (52.50, 13.40)]);
+        let stops = vec![stop(52.50, 13.40, None), stop(52.51, 13.41, None), stop(52.50, 13.40, None)];
+        let matches = match_stops_to_shape(&stops, &shape_points);
+        assert_eq!(matches.iter().map(|m| m.shape_index).collect::<Vec<_>>(), vec![0, 2, 4]);
+        assert!(matches.iter().all(|m| m.distance < 1.0));
+    }
+
+    #[test]
+    fn test_match_out_and_back() {
+        let shape_points = shape(&[(52.50, 13.40), (52.50, 13.41), (52.50, 13.42), (52.50, 13.41), (52.50, 13.40)]);
+        let stops = vec![stop(52.50, 13.40, None), stop(52.50, 13.42, None), stop(52.5001, 13.41, None), stop(52.50, 13.40, None)];
+        let matches = match_stops_to_shape(&stops, &shape_points);
+        assert_eq!(matches.iter().map(|m| m.shape_index).collect::<Vec<_>>(), vec![0, 2, 3, 4]);
+        assert!((matches[2].distance - 11.1).abs() < 0.5);
+    }
+
+    #[test]
+    fn test_match_by_distance_traveled() {
+        let shape_points = shape(&[(52.50, 13.40), (52.50, 13.41), (52.50, 13.42), (52.50, 13.41), (52.50, 13.40)]);
+        let stops = vec![stop(52.50, 13.40, Some(0.0)), stop(52.50, 13.41, Some(290.0)), stop(52.50, 13.40, Some(400.0))];
+        let matches = match_stops_to_shape(&stops, &shape_points);
+        assert_eq!(matches.iter().map(|m| m.shape_index).collect::<Vec<_>>(), vec![0, 3, 4]);
+    }
+}
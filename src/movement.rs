@@ -0,0 +1,301 @@
+use std::time::Duration;
+use crate::geo_utils::vincenty_dist_between_coordinates;
+use crate::gtfs::{parse_duration, ShapePoint, Stop};
+use crate::map_matching::StopMatch;
+
+/// The maximum distance in meters between two points of a trajectory
+pub const SAMPLE_SPACING: f64 = 100.0;
+
+/// Acceleration and braking of a vehicle in m/s²
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MovementProfile {
+    pub acceleration: f64,
+    pub deceleration: f64,
+}
+
+/// The kind of vehicle serving a route, which determines how fast it accelerates and brakes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum VehicleKind {
+    Rail,
+    Tram,
+    Bus,
+}
+
+impl VehicleKind {
+    /// The vehicle kind of a basic or extended GTFS route type. Route types that are neither rail nor tram move like buses.
+    pub fn from_route_type(route_type: i64) -> Self {
+        match route_type {
+            0 | 5 | 900..=999 => VehicleKind::Tram,
+            1 | 2 | 12 | 100..=199 | 400..=499 => VehicleKind::Rail,
+            _ => VehicleKind::Bus,
+        }
+    }
+
+    pub fn profile(&self) -> MovementProfile {
+        match self {
+            VehicleKind::Rail => MovementProfile { acceleration: 1.0, deceleration: 0.9 },
+            VehicleKind::Tram => MovementProfile { acceleration: 1.3, deceleration: 1.3 },
+            VehicleKind::Bus => MovementProfile { acceleration: 1.0, deceleration: 1.2 },
+        }
+    }
+}
+
+// the movement between the departure from a stop and the arrival at the next stop: the vehicle accelerates to the cruise
+// speed, cruises and brakes. If the distance can not be covered in time with the profile, the vehicle accelerates and brakes
+// without cruising and the run is compressed in time to fit the schedule.
+#[derive(Clone, Copy, Debug)]
+struct Run {
+    departure: f64,
+    arrival: f64,
+    start_distance: f64,
+    distance: f64,
+    cruise_speed: f64,
+    time_scale: f64,
+    profile: MovementProfile,
+}
+
+impl Run {
+    fn new(departure: f64, arrival: f64, start_distance: f64, distance: f64, profile: MovementProfile) -> Self {
+        let duration = arrival - departure;
+        //the time lost by accelerating and braking for every m/s of cruise speed
+        let k = 1.0 / (2.0 * profile.acceleration) + 1.0 / (2.0 * profile.deceleration);
+        let discriminant = duration * duration - 4.0 * k * distance;
+        let (cruise_speed, time_scale) = if duration <= 0.0 || distance <= 0.0 {
+            (0.0, 1.0)
+        } else if discriminant >= 0.0 {
+            ((duration - discriminant.sqrt()) / (2.0 * k), 1.0)
+        } else {
+            (distance.sqrt() / k.sqrt(), duration / (2.0 * (k * distance).sqrt()))
+        };
+        Run { departure, arrival, start_distance, distance, cruise_speed, time_scale, profile }
+    }
+
+    fn accelerating_distance(&self) -> f64 {
+        self.cruise_speed.powi(2) / (2.0 * self.profile.acceleration)
+    }
+
+    fn braking_distance(&self) -> f64 {
+        self.cruise_speed.powi(2) / (2.0 * self.profile.deceleration)
+    }
+
+    // the time at which the vehicle has traveled the distance since the departure
+    fn time_at(&self, distance: f64) -> f64 {
+        if self.cruise_speed <= 0.0 {
+            return self.departure;
+        }
+        let distance = distance.clamp(0.0, self.distance);
+        let (s1, s3) = (self.accelerating_distance(), self.braking_distance());
+        let t1 = self.cruise_speed / self.profile.acceleration;
+        let unscaled = if distance <= s1 {
+            (2.0 * distance / self.profile.acceleration).sqrt()
+        } else if distance <= self.distance - s3 {
+            t1 + (distance - s1) / self.cruise_speed
+        } else {
+            let total = t1 + (self.distance - s1 - s3) / self.cruise_speed + self.cruise_speed / self.profile.deceleration;
+            total - (2.0 * (self.distance - distance) / self.profile.deceleration).sqrt()
+        };
+        (self.departure + unscaled * self.time_scale).min(self.arrival)
+    }
+
+    // the distance traveled since the departure at a time between departure and arrival
+    fn distance_at(&self, time: f64) -> f64 {
+        if self.cruise_speed <= 0.0 {
+            return 0.0;
+        }
+        let t = ((time - self.departure) / self.time_scale).max(0.0);
+        let (s1, s3) = (self.accelerating_distance(), self.braking_distance());
+        let t1 = self.cruise_speed / self.profile.acceleration;
+        let t2 = (self.distance - s1 - s3) / self.cruise_speed;
+        let t3 = self.cruise_speed / self.profile.deceleration;
+        let distance = if t <= t1 {
+            self.profile.acceleration * t * t / 2.0
+        } else if t <= t1 + t2 {
+            s1 + (t - t1) * self.cruise_speed
+        } else {
+            let remaining = (t1 + t2 + t3 - t).max(0.0);
+            self.distance - self.profile.deceleration * remaining * remaining / 2.0
+        };
+        distance.clamp(0.0, self.distance)
+    }
+}
+
+/// The movement of a vehicle along the shape of a trip. The vehicle waits at every stop from its arrival until its departure
+/// and moves between two stops according to its movement profile at the cruise speed that makes it arrive on time.
+pub struct TripMovement {
+    shape_points: Vec<ShapePoint>,
+    // distance in meters along the shape to every shape point
+    distances: Vec<f64>,
+    // the shape index, arrival and departure in seconds of every stop
+    stops: Vec<(usize, f64, f64)>,
+    runs: Vec<Run>,
+}
+
+impl TripMovement {
+    /// Stops are matched to the shape points by the map matching, both ordered along the trip
+    pub fn new(stops: &[Stop], matches: &[StopMatch], shape_points: &[ShapePoint], profile: MovementProfile) -> rusqlite::Result<Self, Box<dyn std::error::Error>> {
+        let mut distances = vec![0.0; shape_points.len()];
+        for i in 1..shape_points.len() {
+            let (previous, point) = (&shape_points[i - 1], &shape_points[i]);
+            distances[i] = distances[i - 1] + vincenty_dist_between_coordinates((previous.shape_pt_lat, previous.shape_pt_lon), (point.shape_pt_lat, point.shape_pt_lon));
+        }
+        let mut timed_stops = vec![];
+        for (stop, stop_match) in stops.iter().zip(matches) {
+            let arrival = parse_duration(&stop.arrival_time)?.as_secs_f64();
+            let departure = parse_duration(&stop.departure_time)?.as_secs_f64().max(arrival);
+            timed_stops.push((stop_match.shape_index, arrival, departure));
+        }
+        let runs = timed_stops.windows(2).map(|pair| {
+            let ((from, _, departure), (to, arrival, _)) = (pair[0], pair[1]);
+            Run::new(departure, arrival.max(departure), distances[from], distances[to] - distances[from], profile)
+        }).collect();
+        Ok(TripMovement { shape_points: shape_points.to_vec(), distances, stops: timed_stops, runs })
+    }
+
+    /// The shape points between the first and the last stop with the time at which the vehicle passes them. The point of a
+    /// stop appears twice if the vehicle dwells at the stop, once at the arrival and once at the departure. Where two shape points
+    /// are more than SAMPLE_SPACING apart, e.g. for shapes created from the stops, the position of the vehicle is sampled in
+    /// between so that its trajectory does not jump from stop to stop.
+    pub fn timed_points(&self) -> Vec<ShapePoint> {
+        let mut points: Vec<ShapePoint> = vec![];
+        let mut push = |mut point: ShapePoint, time: f64| {
+            point.time = Some(Duration::from_secs_f64(time.max(0.0)));
+            if !points.last().is_some_and(|last| last.shape_id == point.shape_id && last.shape_pt_sequence == point.shape_pt_sequence && last.time == point.time) {
+                points.push(point);
+            }
+        };
+        for (i, &(index, arrival, departure)) in self.stops.iter().enumerate() {
+            push(self.shape_points[index].clone(), arrival);
+            push(self.shape_points[index].clone(), departure);
+            if let (Some(run), Some(&(next_index, _, _))) = (self.runs.get(i), self.stops.get(i + 1)) {
+                for between in index + 1..=next_index {
+                    for (distance, sample) in self.samples_between(between - 1, between) {
+                        push(sample, run.time_at(distance - run.start_distance));
+                    }
+                    if between < next_index {
+                        push(self.shape_points[between].clone(), run.time_at(self.distances[between] - run.start_distance));
+                    }
+                }
+            }
+        }
+        points
+    }
+
+    // points every SAMPLE_SPACING meters along the shape strictly between two consecutive shape points, with their distance along
+    // the shape. The samples are numbered by their distance along the shape, so they get the same shape id and sequence
+    // number for every trip with this shape and share the cell assigned to them.
+    fn samples_between(&self, from: usize, to: usize) -> Vec<(f64, ShapePoint)> {
+        let (start, end) = (self.distances[from], self.distances[to]);
+        if end - start <= SAMPLE_SPACING {
+            return vec![];
+        }
+        let first = (start / SAMPLE_SPACING).floor() as u64 + 1;
+        (first..).take_while(|k| (*k as f64) * SAMPLE_SPACING < end).map(|k| {
+            let distance = k as f64 * SAMPLE_SPACING;
+            let (lat, lon) = self.position_at_distance(distance);
+            (distance, ShapePoint {
+                shape_id: format!("sampled_{}", self.shape_points[from].shape_id),
+                shape_pt_lat: lat,
+                shape_pt_lon: lon,
+                shape_pt_sequence: k,
+                shape_dist_traveled: None,
+                time: None,
+                synthetic: true,
+            })
+        }).collect()
+    }
+
+    /// The position of the vehicle at a time between the arrival at the first and the departure from the last stop
+    pub fn position_at(&self, time: Duration) -> Option<(f64, f64)> {
+        let time = time.as_secs_f64();
+        let (first, last) = (self.stops.first()?, self.stops.last()?);
+        if time < first.1 || time > last.2 {
+            return None;
+        }
+        //a vehicle between the departure from a stop and the arrival at the next stop is on its way, otherwise it waits at a stop
+        let distance = match self.runs.iter().find(|run| run.departure < time && time < run.arrival) {
+            Some(run) => run.start_distance + run.distance_at(time),
+            None => {
+                let (index, _, _) = self.stops.iter().rev().find(|(_, arrival, _)| *arrival <= time).unwrap_or(first);
+                self.distances[*index]
+            }
+        };
+        Some(self.position_at_distance(distance))
+    }
+
+    fn position_at_distance(&self, distance: f64) -> (f64, f64) {
+        let next = self.distances.partition_point(|d| *d < distance).min(self.shape_points.len() - 1);
+        let point = &self.shape_points[next];
+        if next == 0 || self.distances[next] <= distance {
+            return (point.shape_pt_lat, point.shape_pt_lon);
+        }
+        let previous = &self.shape_points[next - 1];
+        let fraction = (distance - self.distances[next - 1]) / (self.distances[next] - self.distances[next - 1]);
+        (previous.shape_pt_lat + (point.shape_pt_lat - previous.shape_pt_lat) * fraction,
+         previous.shape_pt_lon + (point.shape_pt_lon - previous.shape_pt_lon) * fraction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shape_point(sequence: u64, lon: f64) -> ShapePoint {
+        ShapePoint::test("shape", sequence, (0.0, lon))
+    }
+
+    fn stop(stop_id: &str, lon: f64, arrival_time: &str, departure_time: &str) -> Stop {
+        Stop::test("trip", stop_id, (0.0, lon), arrival_time, departure_time)
+    }
+
+    #[test]
+    fn test_run_profile() {
+        let profile = VehicleKind::Rail.profile();
+        let run = Run::new(0.0, 120.0, 0.0, 2000.0, profile);
+        //the vehicle leaves and arrives at rest and covers the distance in time
+        assert!((run.time_at(2000.0) - 120.0).abs() < 1e-6);
+        assert!((run.distance_at(120.0) - 2000.0).abs() < 1e-6);
+        //it cruises faster than the mean speed and is slow near the stops
+        assert!(run.cruise_speed > 2000.0 / 120.0);
+        assert!(run.time_at(100.0) > 120.0 * 100.0 / 2000.0);
+        assert!((run.distance_at(run.time_at(700.0)) - 700.0).abs() < 1e-6);
+        //a run that is too short for the profile still arrives on time
+        let fast = Run::new(0.0, 10.0, 0.0, 2000.0, profile);
+        assert!((fast.time_at(2000.0) - 10.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_dwell_at_stops() {
+        let shape_points: Vec<ShapePoint> = (0..5).map(|i| shape_point(i, i as f64 * 0.01)).collect();
+        let stops = vec![stop("A", 0.0, "08:00:00", "08:00:00"), stop("B", 0.02, "08:02:00", "08:02:30"), stop("C", 0.04, "08:04:30", "08:04:30")];
+        let matches: Vec<StopMatch> = [0, 2, 4].iter().map(|&shape_index| StopMatch { shape_index, distance: 0.0 }).collect();
+        let movement = TripMovement::new(&stops, &matches, &shape_points, VehicleKind::Tram.profile()).unwrap();
+        let points = movement.timed_points();
+        let times: Vec<(u64, u64)> = points.iter().filter(|p| !p.synthetic).map(|p| (p.shape_pt_sequence, p.time.unwrap().as_secs())).collect();
+        //the point of stop B is passed at the arrival and the departure, the points between stops at half the distance
+        assert_eq!(times, vec![(0, 28800), (1, 28860), (2, 28920), (2, 28950), (3, 29010), (4, 29070)]);
+        assert_eq!(movement.position_at(Duration::from_secs(28930)), Some((0.0, 0.02)));
+        assert!(movement.position_at(Duration::from_secs(29100)).is_none());
+    }
+
+    #[test]
+    fn test_sample_between_stops() {
+        //a shape created from two stops about 1.1km apart
+        let shape_points = vec![shape_point(0, 0.0), shape_point(1, 0.01)];
+        let stops = vec![stop("A", 0.0, "08:00:00", "08:00:00"), stop("B", 0.01, "08:02:00", "08:02:00")];
+        let matches: Vec<StopMatch> = [0, 1].iter().map(|&shape_index| StopMatch { shape_index, distance: 0.0 }).collect();
+        let movement = TripMovement::new(&stops, &matches, &shape_points, VehicleKind::Bus.profile()).unwrap();
+        let points = movement.timed_points();
+        assert_eq!(points.len(), 13);
+        assert_eq!((points[1].shape_id.as_str(), points[1].shape_pt_sequence), ("sampled_shape", 1));
+        //the samples are in order and at the position of the vehicle at their time
+        for pair in points.windows(2) {
+            assert!(pair[0].time < pair[1].time && pair[0].shape_pt_lon < pair[1].shape_pt_lon);
+            let distance = vincenty_dist_between_coordinates((pair[0].shape_pt_lat, pair[0].shape_pt_lon), (pair[1].shape_pt_lat, pair[1].shape_pt_lon));
+            assert!(distance <= SAMPLE_SPACING + 1e-6);
+        }
+        for point in &points[1..points.len() - 1] {
+            let (_, lon) = movement.position_at(point.time.unwrap()).unwrap();
+            assert!((lon - point.shape_pt_lon).abs() < 1e-6);
+        }
+    }
+}
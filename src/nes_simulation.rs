@@ -1,18 +1,67 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::{fs, time};
 use std::ops::Sub;
 use std::time::Duration;
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
-use crate::cell_data::{MultiTripAndCellData, RadioCell, TripAndCellData};
+use crate::cell_data::{CellGlobalId, MultiTripAndCellData, RadioCell, TripAndCellData};
 use serde_with::DurationMilliSeconds;
 use crate::gtfs::{parse_duration, PartialBlock, Stop};
+use crate::sites::Site;
+use crate::geo_utils;
+use crate::handover::HandoverTiming;
+use crate::time_mapping::TimeMapping;
+use crate::attachment_intervals::{compute_attachment_intervals, AttachmentChange, AttachmentInterval};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Deserialize)]
+struct Location {
+    lat: f64,
+    lon: f64,
+}
+
+/// Read a list of (lat, lon) locations from a csv file with the header "lat,lon"
+pub fn read_locations_from_csv(path: &str) -> Result<Vec<(f64, f64)>, Box<dyn std::error::Error>> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let mut locations = vec![];
+    for record in reader.deserialize() {
+        let location: Location = record?;
+        locations.push((location.lat, location.lon));
+    }
+    Ok(locations)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FixedTopology {
     pub nodes: HashMap<u64, Vec<f64>>,
     pub slots: HashMap<u64, u16>,
     pub children: HashMap<u64, Vec<u64>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<TopologyLink>,
+    /// The id of the coordinator/cloud node if it is part of the topology
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root: Option<u64>,
+}
+
+/// Optional properties of a network link. Properties that are not set are omitted from the json output.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct LinkAttributes {
+    #[serde(rename = "latencyInMs", skip_serializing_if = "Option::is_none", default)]
+    pub latency_in_ms: Option<f64>,
+    #[serde(rename = "bandwidthInMbps", skip_serializing_if = "Option::is_none", default)]
+    pub bandwidth_in_mbps: Option<f64>,
+    #[serde(rename = "packetLoss", skip_serializing_if = "Option::is_none", default)]
+    pub packet_loss: Option<f64>,
+}
+
+/// Properties of an edge between a parent and one of its children
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TopologyLink {
+    #[serde(rename = "parentId")]
+    pub parent_id: u64,
+    #[serde(rename = "childId")]
+    pub child_id: u64,
+    #[serde(flatten)]
+    pub attributes: LinkAttributes,
 }
 
 
@@ -32,6 +81,12 @@ pub struct ISQPEvent {
     #[serde(rename = "childId")]
     pub child_id: u64,
     pub action: ISQPEventAction,
+    /// Set when the topology has an edge server tier. True if the old and the new parent of a handover belong to the same edge server.
+    #[serde(rename = "intraServer", skip_serializing_if = "Option::is_none", default)]
+    pub intra_server: Option<bool>,
+    /// Properties of the link created by an add event
+    #[serde(flatten)]
+    pub link: LinkAttributes,
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
@@ -40,9 +95,19 @@ pub enum ISQPEventAction {
     remove,
 }
 
+/// Positions (update, event) of the remove and the add event of a handover
+struct Handover {
+    removal: (usize, usize),
+    addition: (usize, usize),
+    intra_server: bool,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct SimulatedReconnects {
     initial_parents: Vec<(u64, u64)>,
+    /// Properties of the links to the initial parents
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    initial_links: Vec<TopologyLink>,
     pub topology_updates: Vec<TopologyUpdate>,
 }
 
@@ -80,7 +145,7 @@ impl SimulatedReconnects {
         }
         source_placement
     }
-    pub fn from_topology_and_cell_data(topology: FixedTopology, mut cell_data: MultiTripAndCellData, cell_id_to_node_id: HashMap<(u64, u64), u64>, start_time: Duration, batch_interval: Option<Duration>, batch_gap: Option<Duration>, group_size: Option<u16>) -> (Self, HashMap<String, u64>, Option<HashMap<u64, Vec<u64>>>) {
+    pub fn from_topology_and_cell_data(topology: FixedTopology, mut cell_data: MultiTripAndCellData, cell_id_to_node_id: HashMap<CellGlobalId, u64>, start_time: Duration, batch_interval: Option<Duration>, batch_gap: Option<Duration>, group_size: Option<u16>) -> (Self, HashMap<String, u64>, Option<HashMap<u64, Vec<u64>>>) {
         let mut trip_to_node = HashMap::new();
         let mut initial_parents = vec![];
         let mut topology_update_map = BTreeMap::new();
@@ -135,12 +200,16 @@ impl SimulatedReconnects {
                     parent_id,
                     child_id,
                     action: ISQPEventAction::add,
+                    intra_server: None,
+                    link: LinkAttributes::default(),
                 };
 
                 let remove_event = ISQPEvent {
                     parent_id: previous_parent_id,
                     child_id,
                     action: ISQPEventAction::remove,
+                    intra_server: None,
+                    link: LinkAttributes::default(),
                 };
 
                 if let Some(interval) = batch_interval {
@@ -168,9 +237,9 @@ impl SimulatedReconnects {
                             }
                         }
 
-                        //start new batch
-                        batched_rem = None;
-                        batched_add = None;
+                        //start a new batch with the current change, the parent may have changed with the inserted batch
+                        batched_rem = Some(ISQPEvent { parent_id: previous_parent_id, ..remove_event });
+                        batched_add = Some(add_event);
 
                         //increment batch start time until we arrive at the batch containing the current time stamp
                         while timestamp > current_batch_interval_start.unwrap() + interval {
@@ -178,27 +247,21 @@ impl SimulatedReconnects {
                             *current_batch_timestamp.as_mut().unwrap() += batch_gap.unwrap();
                         }
                     }
-                } else {
+                } else if parent_id != previous_parent_id {
                     let update_at_time = topology_update_map.entry(timestamp).or_insert(TopologyUpdate { timestamp, events: vec![] });
-
-                    update_at_time.events.push(ISQPEvent {
-                        parent_id: previous_parent_id,
-                        child_id,
-                        action: ISQPEventAction::remove,
-                    });
-
-                    update_at_time.events.push(ISQPEvent {
-                        parent_id,
-                        child_id,
-                        action: ISQPEventAction::add,
-                    });
+                    update_at_time.events.push(remove_event);
+                    update_at_time.events.push(add_event);
+                    previous_parent_id = parent_id;
                 }
             }
 
             if let Some(rem) = batched_rem {
-                let update_at_time = topology_update_map.entry(current_batch_timestamp.unwrap()).or_insert(TopologyUpdate { timestamp: current_batch_timestamp.unwrap(), events: vec![] });
-                update_at_time.events.push(rem);
-                update_at_time.events.push(batched_add.unwrap());
+                let add = batched_add.unwrap();
+                if rem.parent_id != add.parent_id {
+                    let update_at_time = topology_update_map.entry(current_batch_timestamp.unwrap()).or_insert(TopologyUpdate { timestamp: current_batch_timestamp.unwrap(), events: vec![] });
+                    update_at_time.events.push(rem);
+                    update_at_time.events.push(add);
+                }
             }
 
             if let Some((trip_to_source, node_to_source)) = &mut source_placement_maps {
@@ -213,7 +276,242 @@ impl SimulatedReconnects {
             None
         };
 
-        (SimulatedReconnects { initial_parents, topology_updates: topology_update_map.into_values().collect() }, trip_to_node, source_mapping)
+        (SimulatedReconnects { initial_parents, initial_links: vec![], topology_updates: topology_update_map.into_values().collect() }, trip_to_node, source_mapping)
+    }
+}
+
+impl SimulatedReconnects {
+    pub fn read_from_file(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// The (parent, child) pairs at the start of the simulation
+    pub fn initial_parents(&self) -> &[(u64, u64)] {
+        &self.initial_parents
+    }
+
+    /// Set the properties of the links to the initial parents and of every link created by an add event
+    pub fn set_link_attributes(&mut self, attributes: impl Fn(u64, u64) -> LinkAttributes) {
+        self.initial_links = self.initial_parents.iter().map(|(parent_id, child_id)| TopologyLink {
+            parent_id: *parent_id,
+            child_id: *child_id,
+            attributes: attributes(*parent_id, *child_id),
+        }).collect();
+        for update in self.topology_updates.iter_mut() {
+            for event in update.events.iter_mut().filter(|e| e.action == ISQPEventAction::add) {
+                event.link = attributes(event.parent_id, event.child_id);
+            }
+        }
+    }
+
+    /// Mark every handover whose old and new parent are children of the same edge server as an intra-server move.
+    /// The remove event of a mobile node is paired with its next add event, which may be part of a later update.
+    pub fn mark_intra_server_moves(&mut self, server_of_node: &HashMap<u64, u64>) {
+        //position (update, event) and parent of the pending remove event of every child
+        let mut removed_from: HashMap<u64, ((usize, usize), u64)> = HashMap::new();
+        let mut handovers: Vec<Handover> = vec![];
+        for (u, update) in self.topology_updates.iter().enumerate() {
+            for (i, event) in update.events.iter().enumerate() {
+                match event.action {
+                    ISQPEventAction::remove => {
+                        removed_from.insert(event.child_id, ((u, i), event.parent_id));
+                    }
+                    ISQPEventAction::add => {
+                        if let Some((remove_position, old_parent)) = removed_from.remove(&event.child_id) {
+                            let intra_server = server_of_node.get(&old_parent).is_some() && server_of_node.get(&old_parent) == server_of_node.get(&event.parent_id);
+                            handovers.push(Handover { removal: remove_position, addition: (u, i), intra_server });
+                        }
+                    }
+                }
+            }
+        }
+        for handover in handovers {
+            for (update, index) in [handover.removal, handover.addition] {
+                self.topology_updates[update].events[index].intra_server = Some(handover.intra_server);
+            }
+        }
+    }
+
+    /// Spread the remove and add event of every handover according to the handover timing. The delayed event of a handover is
+    /// emitted at the latest together with the next handover of the same mobile node so that the events of a node stay in order.
+    pub fn apply_handover_timing(&mut self, timing: &mut HandoverTiming) {
+        let mut events: Vec<(Duration, usize, ISQPEvent)> = vec![];
+        //indices of the remove and the add event of every handover, in chronological order
+        let mut handovers: Vec<(usize, usize)> = vec![];
+        for update in self.topology_updates.drain(..) {
+            let mut removed_at: HashMap<u64, usize> = HashMap::new();
+            for event in update.events {
+                let index = events.len();
+                match event.action {
+                    ISQPEventAction::remove => {
+                        removed_at.insert(event.child_id, index);
+                    }
+                    ISQPEventAction::add => {
+                        if let Some(remove_index) = removed_at.remove(&event.child_id) {
+                            handovers.push((remove_index, index));
+                        }
+                    }
+                }
+                events.push((update.timestamp, index, event));
+            }
+        }
+
+        let mut next_handover: Vec<Option<Duration>> = vec![None; handovers.len()];
+        let mut last_handover_of_child: HashMap<u64, usize> = HashMap::new();
+        for (i, (remove_index, _)) in handovers.iter().enumerate() {
+            let (timestamp, _, event) = &events[*remove_index];
+            if let Some(previous) = last_handover_of_child.insert(event.child_id, i) {
+                next_handover[previous] = Some(*timestamp);
+            }
+        }
+
+        for (i, (remove_index, add_index)) in handovers.iter().enumerate() {
+            let delayed_index = if timing.adds_first() { *remove_index } else { *add_index };
+            let mut timestamp = events[delayed_index].0 + timing.next_delay();
+            if let Some(next) = next_handover[i] {
+                timestamp = timestamp.min(next);
+            }
+            events[delayed_index].0 = timestamp;
+        }
+
+        //events at the same time keep their original order, which places delayed events before later handovers of the same node
+        events.sort_by_key(|(timestamp, index, _)| (*timestamp, *index));
+        let mut topology_update_map = BTreeMap::new();
+        for (timestamp, _, event) in events {
+            topology_update_map.entry(timestamp).or_insert(TopologyUpdate { timestamp, events: vec![] }).events.push(event);
+        }
+        self.topology_updates = topology_update_map.into_values().collect();
+    }
+
+    /// The periods during which each mobile node is attached to a parent. Attachments still open at the end of the emulation end with it.
+    pub fn attachment_intervals(&self, emulation_end: Duration, start_time: Duration, to_real: impl Fn(Duration) -> Duration) -> Vec<AttachmentInterval> {
+        let changes = self.topology_updates.iter().flat_map(|update| update.events.iter().map(|event| AttachmentChange {
+            timestamp: update.timestamp,
+            parent_id: event.parent_id,
+            child_id: event.child_id,
+            attached: event.action == ISQPEventAction::add,
+        }));
+        compute_attachment_intervals(&self.initial_parents, changes, emulation_end, start_time, to_real)
+    }
+
+    /// Split updates exceeding the maximum number of events per update or per second of emulated time and move the remaining
+    /// events to later timestamps, spaced by the given interval. A remove and the following add of the same mobile node are
+    /// kept in the same update and the order of all events is preserved.
+    pub fn limit_update_size(&mut self, max_events_per_update: Option<usize>, max_events_per_second: Option<usize>, spacing: Duration) {
+        let max_events_per_update = max_events_per_update.unwrap_or(usize::MAX);
+        let max_events_per_second = max_events_per_second.unwrap_or(usize::MAX);
+        let mut updates: VecDeque<TopologyUpdate> = self.topology_updates.drain(..).collect();
+        let mut pending: VecDeque<Vec<ISQPEvent>> = VecDeque::new();
+        let mut limited: Vec<TopologyUpdate> = vec![];
+        let mut second = 0;
+        let mut events_in_second = 0;
+        let mut timestamp = updates.front().map(|u| u.timestamp).unwrap_or_default();
+        let mut delayed = 0;
+
+        while !updates.is_empty() || !pending.is_empty() {
+            //events of updates that are due are queued behind the events that were delayed
+            while updates.front().is_some_and(|u| u.timestamp <= timestamp) {
+                let update = updates.pop_front().unwrap();
+                let mut events = update.events.into_iter().peekable();
+                while let Some(event) = events.next() {
+                    let is_handover = event.action == ISQPEventAction::remove && events.peek().is_some_and(|next| next.action == ISQPEventAction::add && next.child_id == event.child_id);
+                    if is_handover {
+                        pending.push_back(vec![event, events.next().unwrap()]);
+                    } else {
+                        pending.push_back(vec![event]);
+                    }
+                }
+            }
+            if timestamp.as_secs() != second {
+                second = timestamp.as_secs();
+                events_in_second = 0;
+            }
+
+            let mut update = TopologyUpdate { timestamp, events: vec![] };
+            while let Some(unit) = pending.front() {
+                let fits_update = update.events.len() + unit.len() <= max_events_per_update;
+                let fits_second = events_in_second + unit.len() <= max_events_per_second;
+                //a unit larger than the budget is emitted on its own
+                let oversized = update.events.is_empty() && events_in_second == 0;
+                if !(oversized || fits_update && fits_second) {
+                    break;
+                }
+                let unit = pending.pop_front().unwrap();
+                events_in_second += unit.len();
+                update.events.extend(unit);
+            }
+            if !update.events.is_empty() {
+                limited.push(update);
+            }
+
+            timestamp = if pending.is_empty() {
+                match updates.front() {
+                    Some(next) => next.timestamp,
+                    None => break,
+                }
+            } else {
+                delayed += 1;
+                let next = if events_in_second >= max_events_per_second { Duration::from_secs(second + 1) } else { timestamp + spacing };
+                match updates.front() {
+                    Some(update) => next.min(update.timestamp.max(timestamp + spacing)),
+                    None => next,
+                }
+            };
+        }
+        if delayed > 0 {
+            println!("Split oversized updates into {} additional updates, last update is emitted after {}ms", delayed, limited.last().unwrap().timestamp.as_millis());
+        }
+        self.topology_updates = limited;
+    }
+
+    /// Map the real time of every update to the emulated time and merge the updates into batches emitted every batch gap.
+    /// Several handovers of a mobile node within one batch are merged into a single remove and add.
+    pub fn compress_time(&mut self, mapping: &TimeMapping, batch_gap: Duration) {
+        let mut batches: BTreeMap<Duration, Vec<ISQPEvent>> = BTreeMap::new();
+        for update in self.topology_updates.drain(..) {
+            let emulated = mapping.map(update.timestamp);
+            let timestamp = if batch_gap.is_zero() {
+                emulated
+            } else {
+                Duration::from_millis((emulated.as_millis() / batch_gap.as_millis()) as u64 * batch_gap.as_millis() as u64)
+            };
+            batches.entry(timestamp).or_default().extend(update.events);
+        }
+
+        let mut skipped = 0;
+        for (timestamp, events) in batches {
+            let mut children = vec![];
+            let mut events_of_child: HashMap<u64, Vec<ISQPEvent>> = HashMap::new();
+            for event in events {
+                if !events_of_child.contains_key(&event.child_id) {
+                    children.push(event.child_id);
+                }
+                events_of_child.entry(event.child_id).or_default().push(event);
+            }
+
+            let mut update = TopologyUpdate { timestamp, events: vec![] };
+            for child_id in children {
+                let mut events = events_of_child.remove(&child_id).unwrap();
+                let first = events.first().unwrap();
+                let last = events.last().unwrap();
+                if events.len() > 2 && first.action == ISQPEventAction::remove && last.action == ISQPEventAction::add {
+                    let add = events.pop().unwrap();
+                    events.truncate(1);
+                    events.push(add);
+                }
+                if events.len() == 2 && events[0].action == ISQPEventAction::remove && events[1].action == ISQPEventAction::add && events[0].parent_id == events[1].parent_id {
+                    skipped += 1;
+                    continue;
+                }
+                update.events.extend(events);
+            }
+            if !update.events.is_empty() {
+                self.topology_updates.push(update);
+            }
+        }
+        if skipped > 0 {
+            println!("Skipped {} mobile nodes that had an edge removed and added to the same parent in the same batch", skipped);
+        }
     }
 }
 
@@ -223,27 +521,278 @@ impl FixedTopology {
         Ok(fs::write(path, json_string)?)
     }
 
-    fn create_single_fog_layer(start_id: u64, default_resoucres: u16, radio_cells: &Vec<&RadioCell>) -> (FixedTopology, HashMap<(u64, u64), u64>) {
+    pub fn read_from_file(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    fn create_single_fog_layer(start_id: u64, default_resoucres: u16, radio_cells: &Vec<&RadioCell>) -> (FixedTopology, HashMap<CellGlobalId, u64>) {
         let mut nodes = HashMap::new();
         let mut slots = HashMap::new();
         let mut children = HashMap::new();
         let mut cell_id_to_node_id = HashMap::new();
 
-        //sort by id
+        //sort by cell id and use the remaining parts of the global id to break ties
         let mut radio_cells: Vec<&RadioCell> = radio_cells.iter().cloned().collect();
-        radio_cells.sort_by(|a, b| a.id.cmp(&b.id));
+        radio_cells.sort_by_key(|cell| (cell.id.cell_id, cell.id));
         for (i, cell) in radio_cells.iter().enumerate() {
             let id = i as u64 + start_id;
             nodes.insert(id, vec![cell.lon, cell.lat]);
             slots.insert(id, default_resoucres);
             children.insert(id, vec![]);
-            cell_id_to_node_id.insert((cell.id, cell.mnc), id);
+            cell_id_to_node_id.insert(cell.id, id);
+        }
+        (FixedTopology { nodes, slots, children, links: vec![], root: None }, cell_id_to_node_id)
+    }
+
+    /// Add edge servers at the given (lat, lon) locations and attach every existing node as a child of its closest edge server.
+    /// Returns the mapping from the attached nodes to their edge server.
+    pub fn add_edge_server_tier(&mut self, server_locations: &[(f64, f64)], server_slots: u16, attached_node_slots: u16) -> HashMap<u64, u64> {
+        let mut server_of_node = HashMap::new();
+        let first_server_id = self.nodes.keys().max().unwrap() + 1;
+        let mut attached_nodes: Vec<u64> = self.nodes.keys().copied().collect();
+        attached_nodes.sort();
+
+        for (i, (lat, lon)) in server_locations.iter().enumerate() {
+            let id = first_server_id + i as u64;
+            self.nodes.insert(id, vec![*lon, *lat]);
+            self.slots.insert(id, server_slots);
+            self.children.insert(id, vec![]);
+        }
+
+        for node_id in attached_nodes {
+            let location = &self.nodes[&node_id];
+            let mut closest = None;
+            let mut min_distance = f64::MAX;
+            for (i, (lat, lon)) in server_locations.iter().enumerate() {
+                let distance = geo_utils::vincenty_dist_between_coordinates((location[1], location[0]), (*lat, *lon));
+                if distance < min_distance {
+                    min_distance = distance;
+                    closest = Some(first_server_id + i as u64);
+                }
+            }
+            if let Some(server_id) = closest {
+                self.children.get_mut(&server_id).unwrap().push(node_id);
+                self.slots.insert(node_id, attached_node_slots);
+                server_of_node.insert(node_id, server_id);
+            }
+        }
+        server_of_node
+    }
+
+    /// Add the coordinator as the root node of the topology. If no location is given, the root is placed at the centroid of the other nodes.
+    /// If connect_top_level_nodes is set, all nodes without a parent become children of the root.
+    pub fn add_root(&mut self, root_id: u64, location: Option<(f64, f64)>, root_slots: u16, connect_top_level_nodes: bool) -> Result<(), Box<dyn std::error::Error>> {
+        if self.nodes.contains_key(&root_id) {
+            return Err(format!("Root id {} is already used by another node", root_id).into());
+        }
+        let (lat, lon) = location.unwrap_or_else(|| {
+            let count = self.nodes.len().max(1) as f64;
+            (self.nodes.values().map(|n| n[1]).sum::<f64>() / count, self.nodes.values().map(|n| n[0]).sum::<f64>() / count)
+        });
+        let mut root_children = vec![];
+        if connect_top_level_nodes {
+            let children: HashSet<u64> = self.children.values().flatten().copied().collect();
+            root_children = self.nodes.keys().filter(|id| !children.contains(id)).copied().collect();
+            root_children.sort();
+        }
+        self.nodes.insert(root_id, vec![lon, lat]);
+        self.slots.insert(root_id, root_slots);
+        self.children.insert(root_id, root_children);
+        self.root = Some(root_id);
+        Ok(())
+    }
+
+    /// create one fog node per site. All cells of a site map to the same node which gets the summed resources of the cells
+    fn create_single_fog_layer_from_sites(start_id: u64, default_resoucres: u16, sites: &[Site]) -> (FixedTopology, HashMap<CellGlobalId, u64>) {
+        let mut nodes = HashMap::new();
+        let mut slots = HashMap::new();
+        let mut children = HashMap::new();
+        let mut cell_id_to_node_id = HashMap::new();
+
+        for (i, site) in sites.iter().enumerate() {
+            let id = i as u64 + start_id;
+            nodes.insert(id, vec![site.lon, site.lat]);
+            let site_slots = (0..site.cells.len()).fold(0u16, |acc, _| acc.saturating_add(default_resoucres));
+            slots.insert(id, site_slots);
+            children.insert(id, vec![]);
+            for cell_id in &site.cells {
+                cell_id_to_node_id.insert(*cell_id, id);
+            }
         }
-        (FixedTopology { nodes, slots, children }, cell_id_to_node_id)
+        (FixedTopology { nodes, slots, children, links: vec![], root: None }, cell_id_to_node_id)
+    }
+}
+
+/// Parse a comma separated latitude and longitude, e.g. the location of the root node given on the command line
+pub fn parse_location(value: &str) -> Result<(f64, f64), String> {
+    let parts: Vec<&str> = value.split(',').collect();
+    if parts.len() != 2 {
+        return Err(format!("expected latitude and longitude separated by a comma, got '{}'", value));
     }
+    let lat = parts[0].trim().parse::<f64>().map_err(|e| e.to_string())?;
+    let lon = parts[1].trim().parse::<f64>().map_err(|e| e.to_string())?;
+    Ok((lat, lon))
 }
 
-pub fn create_single_fog_layer_topology_from_cell_data(start_id: u64, default_resources: u16, cell_data: &MultiTripAndCellData) -> (FixedTopology, HashMap<(u64, u64), u64>) {
+pub fn create_single_fog_layer_topology_from_cell_data(start_id: u64, default_resources: u16, cell_data: &MultiTripAndCellData) -> (FixedTopology, HashMap<CellGlobalId, u64>) {
     FixedTopology::create_single_fog_layer(start_id, default_resources, &cell_data.radio_cells.values().collect())
 }
 
+pub fn create_single_fog_layer_topology_from_sites(start_id: u64, default_resources: u16, sites: &[Site]) -> (FixedTopology, HashMap<CellGlobalId, u64>) {
+    FixedTopology::create_single_fog_layer_from_sites(start_id, default_resources, sites)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell_data::Radio;
+    use crate::gtfs::ShapePoint;
+
+    fn handover(parent_id: u64, new_parent_id: u64, child_id: u64) -> Vec<ISQPEvent> {
+        let event = |parent_id, action| ISQPEvent { parent_id, child_id, action, intra_server: None, link: LinkAttributes::default() };
+        vec![event(parent_id, ISQPEventAction::remove), event(new_parent_id, ISQPEventAction::add)]
+    }
+
+    #[test]
+    fn test_schedule_reconnects() {
+        let cell = |cell_id| CellGlobalId { radio: Radio::LTE, mcc: 262, mnc: 2, area_code: 1, cell_id };
+        let vehicle = |block_id: &str, points: &[(u64, u64)]| {
+            let shape_points: Vec<ShapePoint> = points.iter().enumerate()
+                .map(|(i, (seconds, _))| ShapePoint { time: Some(Duration::from_secs(*seconds)), ..ShapePoint::test(block_id, i as u64, (0.0, 0.0)) })
+                .collect();
+            let cell_data = points.iter().enumerate().map(|(i, (_, cell_id))| ((block_id.to_string(), i as u64), cell(*cell_id))).collect();
+            TripAndCellData { trip: PartialBlock { block_id: block_id.to_string(), route_id: "S41".to_string(), stops: vec![], shape_points }, cell_data }
+        };
+        let reconnects = |batch_interval: Option<u64>| {
+            let nodes = HashMap::from([(1, vec![0.0, 0.0]), (2, vec![0.0, 0.01]), (3, vec![0.0, 0.02])]);
+            let topology = FixedTopology { nodes, slots: HashMap::new(), children: HashMap::new(), links: vec![], root: None };
+            let cell_data = MultiTripAndCellData {
+                trips: HashMap::from([("S41".to_string(), vec![vehicle("a", &[(0, 1), (5, 2), (12, 3)]), vehicle("b", &[(0, 1), (5, 2), (12, 2)])])]),
+                radio_cells: HashMap::new(),
+            };
+            let cell_id_to_node_id = HashMap::from([(cell(1), 1), (cell(2), 2), (cell(3), 3)]);
+            let (reconnects, _, _) = SimulatedReconnects::from_topology_and_cell_data(topology, cell_data, cell_id_to_node_id, Duration::ZERO, batch_interval.map(Duration::from_secs), batch_interval.map(|_| Duration::from_secs(1)), None);
+            reconnects.topology_updates.iter()
+                .map(|u| (u.timestamp.as_secs(), u.events.iter().map(|e| (e.child_id, e.parent_id)).collect()))
+                .collect::<Vec<(u64, Vec<(u64, u64)>)>>()
+        };
+        //without batching every cell change is emitted at its own time
+        assert_eq!(reconnects(None), vec![(5, vec![(4, 1), (4, 2), (5, 1), (5, 2)]), (12, vec![(4, 2), (4, 3)])]);
+        //changes inside one batch are merged into a single handover from the first to the last parent
+        assert_eq!(reconnects(Some(20)), vec![(0, vec![(4, 1), (4, 3), (5, 1), (5, 2)])]);
+    }
+
+    #[test]
+    fn test_batch_reconnects() {
+        let cell = |cell_id| CellGlobalId { radio: Radio::LTE, mcc: 262, mnc: 2, area_code: 1, cell_id };
+        let topology = FixedTopology {
+            nodes: HashMap::from([(1, vec![0.0, 0.0]), (2, vec![0.0, 0.01]), (3, vec![0.0, 0.02])]),
+            slots: HashMap::new(),
+            children: HashMap::new(),
+            links: vec![],
+            root: None,
+        };
+        let vehicle = |block_id: &str, points: &[(u64, u64)]| {
+            let shape_points: Vec<ShapePoint> = points.iter().enumerate()
+                .map(|(i, (seconds, _))| ShapePoint { time: Some(Duration::from_secs(*seconds)), ..ShapePoint::test(block_id, i as u64, (0.0, 0.0)) })
+                .collect();
+            let cell_data = points.iter().enumerate().map(|(i, (_, cell_id))| ((block_id.to_string(), i as u64), cell(*cell_id))).collect();
+            TripAndCellData { trip: PartialBlock { block_id: block_id.to_string(), route_id: "S41".to_string(), stops: vec![], shape_points }, cell_data }
+        };
+        let cell_data = MultiTripAndCellData {
+            //the change at 12 s opens the second batch, the second vehicle stays at its parent in the second batch
+            trips: HashMap::from([("S41".to_string(), vec![vehicle("a", &[(0, 1), (5, 2), (12, 3)]), vehicle("b", &[(0, 1), (5, 2), (12, 2)])])]),
+            radio_cells: HashMap::new(),
+        };
+        let cell_id_to_node_id = HashMap::from([(cell(1), 1), (cell(2), 2), (cell(3), 3)]);
+        let (reconnects, trip_to_node, _) = SimulatedReconnects::from_topology_and_cell_data(topology, cell_data, cell_id_to_node_id, Duration::ZERO, Some(Duration::from_secs(10)), Some(Duration::from_secs(1)), None);
+        assert_eq!(trip_to_node, HashMap::from([("a".to_string(), 4), ("b".to_string(), 5)]));
+        let updates: Vec<(u64, Vec<(u64, u64)>)> = reconnects.topology_updates.iter()
+            .map(|u| (u.timestamp.as_secs(), u.events.iter().map(|e| (e.child_id, e.parent_id)).collect()))
+            .collect();
+        assert_eq!(updates, vec![(0, vec![(4, 1), (4, 2), (5, 1), (5, 2)]), (1, vec![(4, 2), (4, 3)])]);
+    }
+
+    #[test]
+    fn test_compress_time() {
+        let mut reconnects = SimulatedReconnects {
+            initial_parents: vec![],
+            initial_links: vec![],
+            topology_updates: vec![
+                TopologyUpdate { timestamp: Duration::ZERO, events: [handover(1, 2, 10), handover(1, 2, 11)].concat() },
+                //lands in the same batch: node 10 moves on, node 11 returns to its parent
+                TopologyUpdate { timestamp: Duration::from_secs(5), events: [handover(2, 3, 10), handover(2, 1, 11)].concat() },
+                TopologyUpdate { timestamp: Duration::from_secs(20), events: handover(3, 2, 10) },
+            ],
+        };
+        let mapping = TimeMapping::uniform(Duration::ZERO, Duration::from_secs(100), Duration::from_secs(10));
+        reconnects.compress_time(&mapping, Duration::from_secs(1));
+        let updates: Vec<(u128, Vec<(u64, u64)>)> = reconnects.topology_updates.iter()
+            .map(|u| (u.timestamp.as_millis(), u.events.iter().map(|e| (e.child_id, e.parent_id)).collect()))
+            .collect();
+        assert_eq!(updates, vec![(0, vec![(10, 1), (10, 3)]), (2000, vec![(10, 3), (10, 2)])]);
+        assert!(reconnects.topology_updates[0].events[0].action == ISQPEventAction::remove);
+    }
+
+    #[test]
+    fn test_limit_update_size() {
+        let mut reconnects = SimulatedReconnects {
+            initial_parents: vec![],
+            initial_links: vec![],
+            topology_updates: vec![
+                TopologyUpdate { timestamp: Duration::ZERO, events: [handover(2, 3, 10), handover(2, 3, 11), handover(2, 3, 12)].concat() },
+                TopologyUpdate { timestamp: Duration::from_millis(50), events: handover(3, 4, 10) },
+            ],
+        };
+        reconnects.limit_update_size(Some(4), None, Duration::from_millis(100));
+        let timestamps: Vec<u128> = reconnects.topology_updates.iter().map(|u| u.timestamp.as_millis()).collect();
+        assert_eq!(timestamps, vec![0, 100]);
+        assert_eq!(reconnects.topology_updates[0].events.len(), 4);
+        let last = &reconnects.topology_updates[1].events;
+        assert_eq!((last[0].child_id, last[2].child_id, last[2].parent_id), (12, 10, 3));
+        assert!(last[3].action == ISQPEventAction::add);
+    }
+
+    #[test]
+    fn test_mark_intra_server_moves() {
+        let mut first_handover = handover(1, 2, 10);
+        let add = first_handover.pop().unwrap();
+        let remove = first_handover.pop().unwrap();
+        let mut reconnects = SimulatedReconnects {
+            initial_parents: vec![],
+            initial_links: vec![],
+            topology_updates: vec![
+                //break before make: the add event of node 10 follows in the next update
+                TopologyUpdate { timestamp: Duration::ZERO, events: [vec![remove], handover(1, 3, 11)].concat() },
+                TopologyUpdate { timestamp: Duration::from_millis(100), events: [vec![add], handover(2, 3, 10)].concat() },
+            ],
+        };
+        //towers 1 and 2 belong to server 100, tower 3 to server 101
+        reconnects.mark_intra_server_moves(&HashMap::from([(1, 100), (2, 100), (3, 101)]));
+        let marks: Vec<Vec<(u64, Option<bool>)>> = reconnects.topology_updates.iter()
+            .map(|u| u.events.iter().map(|e| (e.child_id, e.intra_server)).collect())
+            .collect();
+        assert_eq!(marks, vec![
+            vec![(10, Some(true)), (11, Some(false)), (11, Some(false))],
+            vec![(10, Some(true)), (10, Some(false)), (10, Some(false))],
+        ]);
+    }
+
+    #[test]
+    fn test_add_root() {
+        let mut topology = FixedTopology {
+            nodes: HashMap::from([(2, vec![13.0, 52.0]), (3, vec![13.2, 52.2])]),
+            slots: HashMap::new(),
+            children: HashMap::from([(2, vec![3]), (3, vec![])]),
+            links: vec![],
+            root: None,
+        };
+        assert_eq!(parse_location("52.5, 13.4"), Ok((52.5, 13.4)));
+        assert!(parse_location("52.5").is_err());
+        assert!(topology.add_root(2, None, 10, true).is_err());
+        assert_eq!(topology.root, None);
+        topology.add_root(1, None, 10, true).unwrap();
+        assert_eq!(topology.children[&1], vec![2]);
+        assert!((topology.nodes[&1][0] - 13.1).abs() < 1e-9 && (topology.nodes[&1][1] - 52.1).abs() < 1e-9);
+    }
+}
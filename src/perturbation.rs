@@ -0,0 +1,121 @@
+use std::time::Duration;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rand_distr::{Distribution, LogNormal, Normal};
+use crate::gtfs::{duration_to_string, parse_duration, TripSchedule};
+
+/// A log-normal delay given by its median and the standard deviation of its logarithm
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DelayDistribution {
+    pub median: Duration,
+    pub sigma: f64,
+}
+
+impl DelayDistribution {
+    fn log_normal(&self) -> LogNormal<f64> {
+        LogNormal::new(self.median.as_secs_f64().max(f64::MIN_POSITIVE).ln(), self.sigma).unwrap()
+    }
+}
+
+/// Randomly deviates the stop times of a schedule from the timetable. Every trip starts with a delay, every segment between two
+/// stops adds running time and the dwell time at every stop varies. Delays can be propagated to the next trip of the same
+/// vehicle, which then departs at the earliest when the previous trip arrived. The perturbation is reproducible for a given seed.
+pub struct SchedulePerturbation {
+    trip_delay: Option<LogNormal<f64>>,
+    segment_delay: Option<LogNormal<f64>>,
+    dwell_noise: Option<Normal<f64>>,
+    propagate_delays: bool,
+    rng: StdRng,
+}
+
+impl SchedulePerturbation {
+    /// The dwell noise is the standard deviation of a normally distributed change of the dwell time
+    pub fn new(trip_delay: Option<DelayDistribution>, segment_delay: Option<DelayDistribution>, dwell_noise: Option<Duration>, propagate_delays: bool, seed: u64) -> Self {
+        SchedulePerturbation {
+            trip_delay: trip_delay.map(|d| d.log_normal()),
+            segment_delay: segment_delay.map(|d| d.log_normal()),
+            dwell_noise: dwell_noise.map(|noise| Normal::new(0.0, noise.as_secs_f64()).unwrap()),
+            propagate_delays,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    fn sample(&mut self, distribution: Option<LogNormal<f64>>) -> f64 {
+        distribution.map_or(0.0, |d| d.sample(&mut self.rng))
+    }
+
+    /// Perturb the stop times of the trips of one vehicle. The trips are sorted into the order in which they are served.
+    pub fn perturb_block(&mut self, trips: &mut [TripSchedule]) -> Result<(), Box<dyn std::error::Error>> {
+        trips.sort_by_cached_key(|trip| trip.stops.first().and_then(|stop| parse_duration(&stop.departure_time).ok()));
+        let mut previous_arrival: Option<f64> = None;
+        for trip in trips.iter_mut() {
+            let mut times = vec![];
+            for stop in &trip.stops {
+                times.push((parse_duration(&stop.arrival_time)?.as_secs_f64(), parse_duration(&stop.departure_time)?.as_secs_f64()));
+            }
+            let Some(first_departure) = times.first().map(|(_, departure)| *departure) else {
+                continue;
+            };
+
+            //the delay at the departure from the previous stop
+            let mut delay = self.sample(self.trip_delay);
+            if let (true, Some(previous_arrival)) = (self.propagate_delays, previous_arrival) {
+                delay = delay.max(previous_arrival - first_departure);
+            }
+            for (i, (arrival, departure)) in times.iter_mut().enumerate() {
+                if i > 0 {
+                    delay += self.sample(self.segment_delay);
+                }
+                let dwell = *departure - *arrival + self.dwell_noise.map_or(0.0, |d| d.sample(&mut self.rng));
+                *arrival += delay;
+                let perturbed_departure = *arrival + dwell.max(0.0);
+                delay = perturbed_departure - *departure;
+                *departure = perturbed_departure;
+            }
+            previous_arrival = times.last().map(|(arrival, _)| *arrival);
+
+            for (stop, (arrival, departure)) in trip.stops.iter_mut().zip(times) {
+                stop.arrival_time = duration_to_string(Duration::from_secs_f64(arrival.max(0.0)));
+                stop.departure_time = duration_to_string(Duration::from_secs_f64(departure.max(0.0)));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::Stop;
+
+    fn trip(times: &[(&str, &str)]) -> TripSchedule {
+        TripSchedule {
+            stops: times.iter().map(|(arrival_time, departure_time)| Stop::test("trip", arrival_time, (52.5, 13.4), arrival_time, departure_time)).collect(),
+            ..TripSchedule::test("trip", &[])
+        }
+    }
+
+    #[test]
+    fn test_delay_propagation() {
+        let delay = DelayDistribution { median: Duration::from_secs(300), sigma: 0.0 };
+        let mut trips = vec![trip(&[("08:00:00", "08:00:00"), ("08:10:00", "08:11:00")]), trip(&[("08:12:00", "08:12:00"), ("08:20:00", "08:20:00")])];
+        SchedulePerturbation::new(Some(delay), Some(delay), None, true, 1).perturb_block(&mut trips).unwrap();
+        //5 minutes at the start and 5 more on the segment
+        assert_eq!((trips[0].stops[1].arrival_time.as_str(), trips[0].stops[1].departure_time.as_str()), ("08:20:00", "08:21:00"));
+        //the second trip departs when the vehicle arrives and then adds its own segment delay
+        assert_eq!(trips[1].stops[0].departure_time, "08:20:00");
+        assert_eq!(trips[1].stops[1].arrival_time, "08:33:00");
+    }
+
+    #[test]
+    fn test_seeded_dwell_noise() {
+        let perturb = |seed: u64| {
+            let mut trips = vec![trip(&[("08:00:00", "08:00:00"), ("08:10:00", "08:12:00"), ("08:20:00", "08:20:00")])];
+            SchedulePerturbation::new(None, None, Some(Duration::from_secs(60)), false, seed).perturb_block(&mut trips).unwrap();
+            trips[0].stops.iter().map(|s| (s.arrival_time.clone(), s.departure_time.clone())).collect::<Vec<_>>()
+        };
+        let times = perturb(7);
+        assert_eq!(times, perturb(7));
+        assert!(times.iter().all(|(arrival, departure)| arrival <= departure));
+    }
+}
@@ -0,0 +1,112 @@
+use std::time::Duration;
+use serde::Serialize;
+use crate::gtfs::{duration_to_string, parse_duration};
+
+/// A time window on a day of the week that is simulated as a scenario of its own
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScenarioWindow {
+    pub day_of_the_week: String,
+    pub start_time: Duration,
+    pub end_time: Duration,
+}
+
+impl ScenarioWindow {
+    /// The name of the directory of the scenario, e.g. day1_060000_070000
+    pub fn name(&self) -> String {
+        let time = |time: Duration| duration_to_string(time).replace(':', "");
+        format!("day{}_{}_{}", self.day_of_the_week, time(self.start_time), time(self.end_time))
+    }
+}
+
+/// Parse a window given as start and end time of the day separated by a dash, e.g. 07:00:00-07:20:00
+pub fn parse_window(window: &str) -> Result<(Duration, Duration), Box<dyn std::error::Error>> {
+    let (start_time, end_time) = window.split_once('-').ok_or("Invalid window format")?;
+    let (start_time, end_time) = (parse_duration(start_time.trim())?, parse_duration(end_time.trim())?);
+    if end_time <= start_time {
+        return Err(format!("Window {} ends before it starts", window).into());
+    }
+    Ok((start_time, end_time))
+}
+
+/// Windows of the given length starting every period from the start time. The last window ends at the latest at the end time.
+pub fn periodic_windows(start_time: Duration, end_time: Duration, period: Duration, length: Duration) -> Vec<(Duration, Duration)> {
+    let mut windows = vec![];
+    let mut window_start = start_time;
+    while !period.is_zero() && window_start + length <= end_time {
+        windows.push((window_start, window_start + length));
+        window_start += period;
+    }
+    windows
+}
+
+/// Every window on every day, ordered by day
+pub fn scenario_windows(days_of_the_week: &[String], windows: &[(Duration, Duration)]) -> Vec<ScenarioWindow> {
+    days_of_the_week.iter().flat_map(|day_of_the_week| windows.iter().map(|(start_time, end_time)| ScenarioWindow {
+        day_of_the_week: day_of_the_week.clone(),
+        start_time: *start_time,
+        end_time: *end_time,
+    })).collect()
+}
+
+/// One row of the summary table of a run with several scenarios
+#[derive(Serialize, Clone, Debug)]
+pub struct ScenarioSummary {
+    pub scenario: String,
+    pub day_of_the_week: String,
+    pub start_time: String,
+    pub end_time: String,
+    pub mobile_nodes: usize,
+    pub radio_cells: usize,
+    pub topology_updates: usize,
+    pub events: usize,
+    pub mean_reconnects_per_second: f64,
+    pub peak_reconnects_per_second: usize,
+}
+
+impl ScenarioSummary {
+    pub fn new(window: &ScenarioWindow) -> Self {
+        ScenarioSummary {
+            scenario: window.name(),
+            day_of_the_week: window.day_of_the_week.clone(),
+            start_time: duration_to_string(window.start_time),
+            end_time: duration_to_string(window.end_time),
+            mobile_nodes: 0,
+            radio_cells: 0,
+            topology_updates: 0,
+            events: 0,
+            mean_reconnects_per_second: 0.0,
+            peak_reconnects_per_second: 0,
+        }
+    }
+}
+
+pub fn write_summary_to_csv(summaries: &[ScenarioSummary], path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = csv::Writer::from_path(path)?;
+    for summary in summaries {
+        writer.serialize(summary)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hourly_windows_on_weekdays() {
+        let hours = |h: u64| Duration::from_secs(h * 3600);
+        let windows = periodic_windows(hours(6), hours(22), hours(1), Duration::from_secs(1200));
+        assert_eq!(windows.len(), 16);
+        assert_eq!(windows[15], (hours(21), hours(21) + Duration::from_secs(1200)));
+        //a window longer than the rest of the range is not created
+        assert_eq!(periodic_windows(hours(6), hours(22), hours(3), hours(3)).len(), 5);
+
+        let days: Vec<String> = (1..=5).map(|d| d.to_string()).collect();
+        let scenarios = scenario_windows(&days, &windows);
+        assert_eq!(scenarios.len(), 80);
+        assert_eq!(scenarios[0].name(), "day1_060000_062000");
+        assert_eq!(parse_window("07:00:00-07:20:00").unwrap(), (hours(7), hours(7) + Duration::from_secs(1200)));
+        assert!(parse_window("08:00:00-07:00:00").is_err());
+    }
+}
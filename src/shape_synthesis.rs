@@ -0,0 +1,143 @@
+use std::fs;
+use geo::HaversineIntermediate;
+use geojson::{GeoJson, Value};
+use crate::gtfs::{ShapePoint, Stop};
+use crate::map_matching::match_stops_to_shape;
+
+/// Polylines whose stops are on average further away than this are not used to synthesize a shape
+const MAX_MEAN_POLYLINE_DISTANCE: f64 = 500.0;
+
+/// A rail or road line, optionally restricted to the route with the given short name
+#[derive(Clone, Debug)]
+pub struct RoutePolyline {
+    pub route: Option<String>,
+    /// (lat, lon) coordinates
+    pub points: Vec<(f64, f64)>,
+}
+
+/// Builds trajectories for trips that have no shape in the feed
+#[derive(Clone, Debug, Default)]
+pub struct ShapeSynthesis {
+    /// Insert points along the great circle between two consecutive stops so they are at most this many meters apart
+    pub densify_spacing: Option<f64>,
+    /// Lines to follow instead of the straight connection between the stops
+    pub polylines: Vec<RoutePolyline>,
+}
+
+/// Read the LineString and MultiLineString features of a GeoJSON file, e.g. rail tracks exported from OpenStreetMap. A feature
+/// with a "route" property is only used for the route with this short name, features without it are used for all routes.
+pub fn read_polylines(path: &str) -> Result<Vec<RoutePolyline>, Box<dyn std::error::Error>> {
+    let geojson: GeoJson = fs::read_to_string(path)?.parse()?;
+    let GeoJson::FeatureCollection(collection) = geojson else {
+        return Err("Polyline file needs to contain a feature collection".into());
+    };
+    let mut polylines = vec![];
+    for feature in collection.features {
+        let route = feature.property("route").and_then(|r| r.as_str()).map(|r| r.to_string());
+        let lines = match feature.geometry.map(|g| g.value) {
+            Some(Value::LineString(line)) => vec![line],
+            Some(Value::MultiLineString(lines)) => lines,
+            _ => continue,
+        };
+        for line in lines {
+            let points = line.iter().map(|position| (position[1], position[0])).collect();
+            polylines.push(RoutePolyline { route: route.clone(), points });
+        }
+    }
+    println!("Read {} polylines from {}", polylines.len(), path);
+    Ok(polylines)
+}
+
+fn to_shape_points(trip_id: &str, points: impl IntoIterator<Item=(f64, f64)>) -> Vec<ShapePoint> {
+    points.into_iter().enumerate().map(|(i, (lat, lon))| ShapePoint {
+        shape_id: format!("synthetic_{}", trip_id),
+        shape_pt_lat: lat,
+        shape_pt_lon: lon,
+        shape_pt_sequence: i as u64,
+        shape_dist_traveled: None,
+        time: None,
+        synthetic: true,
+    }).collect()
+}
+
+impl ShapeSynthesis {
+    /// Create a shape for the stops of a trip. The part of the best matching polyline between the first and the last stop is used
+    /// if there is one close enough to the stops, otherwise the stops are connected directly.
+    pub fn synthesize_shape(&self, trip_id: &str, route_id: &str, stops: &[Stop]) -> Vec<ShapePoint> {
+        if let Some(points) = self.follow_polyline(trip_id, route_id, stops) {
+            return to_shape_points(trip_id, points);
+        }
+
+        let mut points = vec![];
+        for (i, stop) in stops.iter().enumerate() {
+            points.push((stop.lat, stop.lon));
+            if let (Some(spacing), Some(next)) = (self.densify_spacing, stops.get(i + 1)) {
+                let from = geo::Point::new(stop.lon, stop.lat);
+                let to = geo::Point::new(next.lon, next.lat);
+                points.extend(from.haversine_intermediate_fill(&to, spacing, false).iter().map(|p| (p.y(), p.x())));
+            }
+        }
+        to_shape_points(trip_id, points)
+    }
+
+    fn follow_polyline(&self, trip_id: &str, route_id: &str, stops: &[Stop]) -> Option<Vec<(f64, f64)>> {
+        let route_polylines: Vec<&RoutePolyline> = self.polylines.iter().filter(|p| p.route.as_deref() == Some(route_id)).collect();
+        let candidates = if route_polylines.is_empty() {
+            self.polylines.iter().filter(|p| p.route.is_none()).collect()
+        } else {
+            route_polylines
+        };
+
+        //try every line in both directions and keep the one closest to the stops
+        let mut best: Option<(f64, Vec<(f64, f64)>)> = None;
+        for polyline in candidates {
+            for reversed in [false, true] {
+                let mut points = polyline.points.clone();
+                if reversed {
+                    points.reverse();
+                }
+                let matches = match_stops_to_shape(stops, &to_shape_points(trip_id, points.iter().copied()));
+                if matches.is_empty() {
+                    continue;
+                }
+                let mean_distance = matches.iter().map(|m| m.distance).sum::<f64>() / matches.len() as f64;
+                if mean_distance <= MAX_MEAN_POLYLINE_DISTANCE && !best.as_ref().is_some_and(|(distance, _)| mean_distance >= *distance) {
+                    let first = matches.first().unwrap().shape_index;
+                    let last = matches.last().unwrap().shape_index;
+                    best = Some((mean_distance, points[first..=last].to_vec()));
+                }
+            }
+        }
+        best.map(|(_, points)| points)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo_utils::vincenty_dist_between_coordinates;
+
+    fn stop(lat: f64, lon: f64) -> Stop {
+        Stop::test("trip", &format!("{},{}", lat, lon), (lat, lon), "08:00:00", "08:00:00")
+    }
+
+    #[test]
+    fn test_densify_between_stops() {
+        let synthesis = ShapeSynthesis { densify_spacing: Some(100.0), polylines: vec![] };
+        let shape = synthesis.synthesize_shape("trip", "S41", &[stop(52.50, 13.40), stop(52.50, 13.41)]);
+        assert!(shape.iter().all(|p| p.synthetic));
+        assert_eq!((shape.first().unwrap().shape_pt_lon, shape.last().unwrap().shape_pt_lon), (13.40, 13.41));
+        for pair in shape.windows(2) {
+            assert!(vincenty_dist_between_coordinates((pair[0].shape_pt_lat, pair[0].shape_pt_lon), (pair[1].shape_pt_lat, pair[1].shape_pt_lon)) <= 100.0);
+        }
+    }
+
+    #[test]
+    fn test_follow_reversed_polyline() {
+        let polyline = RoutePolyline { route: Some("S41".to_string()), points: vec![(52.52, 13.42), (52.51, 13.42), (52.50, 13.42), (52.50, 13.41), (52.50, 13.40)] };
+        let synthesis = ShapeSynthesis { densify_spacing: None, polylines: vec![polyline] };
+        let shape = synthesis.synthesize_shape("trip", "S41", &[stop(52.50, 13.40), stop(52.51, 13.42)]);
+        let points: Vec<(f64, f64)> = shape.iter().map(|p| (p.shape_pt_lat, p.shape_pt_lon)).collect();
+        assert_eq!(points, vec![(52.50, 13.40), (52.50, 13.41), (52.50, 13.42), (52.51, 13.42)]);
+    }
+}
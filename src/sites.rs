@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+use crate::cell_data::{CellGlobalId, Radio, RadioCell};
+use crate::geo_utils;
+
+/// Strategy used to decide which radio cells belong to the same physical site
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SiteAggregation {
+    /// Cells whose location is within the given distance of the first cell of a site are merged into that site
+    Location { tolerance_in_meters: f64 },
+    /// LTE cells sharing the eNodeB id (the cell id without the 8 bit sector id) are merged. Cells of other radio types stay on their own.
+    ENodeB,
+}
+
+/// A physical mast hosting one or more radio cells
+#[derive(Clone, Debug)]
+pub struct Site {
+    pub lat: f64,
+    pub lon: f64,
+    pub cells: Vec<CellGlobalId>,
+}
+
+impl Site {
+    fn from_cells(cells: &[&RadioCell]) -> Self {
+        let lat = cells.iter().map(|c| c.lat).sum::<f64>() / cells.len() as f64;
+        let lon = cells.iter().map(|c| c.lon).sum::<f64>() / cells.len() as f64;
+        Site {
+            lat,
+            lon,
+            cells: cells.iter().map(|c| c.id).collect(),
+        }
+    }
+}
+
+// the eNodeB id is encoded in the upper 20 bits of the 28 bit E-UTRAN cell identifier
+fn enodeb_key(id: &CellGlobalId) -> (Radio, u64, u64, u64) {
+    match id.radio {
+        Radio::LTE => (id.radio, id.mcc, id.mnc, id.cell_id >> 8),
+        _ => (id.radio, id.mcc, id.mnc, id.cell_id),
+    }
+}
+
+/// Group radio cells into sites. The returned sites are ordered by their smallest cell id so that node ids are stable between runs.
+pub fn group_cells_into_sites<'a>(radio_cells: impl IntoIterator<Item=&'a RadioCell>, aggregation: SiteAggregation) -> Vec<Site> {
+    let mut radio_cells: Vec<&RadioCell> = radio_cells.into_iter().collect();
+    radio_cells.sort_by_key(|cell| (cell.id.cell_id, cell.id));
+
+    let groups: Vec<Vec<&RadioCell>> = match aggregation {
+        SiteAggregation::Location { tolerance_in_meters } => {
+            let mut groups: Vec<Vec<&RadioCell>> = vec![];
+            for cell in radio_cells {
+                let site = groups.iter_mut().find(|group| {
+                    let anchor = group.first().unwrap();
+                    geo_utils::vincenty_dist_between_coordinates((anchor.lat, anchor.lon), (cell.lat, cell.lon)) <= tolerance_in_meters
+                });
+                match site {
+                    Some(group) => group.push(cell),
+                    None => groups.push(vec![cell]),
+                }
+            }
+            groups
+        }
+        SiteAggregation::ENodeB => {
+            let mut groups: BTreeMap<(Radio, u64, u64, u64), Vec<&RadioCell>> = BTreeMap::new();
+            for cell in radio_cells {
+                groups.entry(enodeb_key(&cell.id)).or_default().push(cell);
+            }
+            let mut groups: Vec<Vec<&RadioCell>> = groups.into_values().collect();
+            groups.sort_by_key(|group| (group[0].id.cell_id, group[0].id));
+            groups
+        }
+    };
+
+    let sites: Vec<Site> = groups.iter().map(|group| Site::from_cells(group)).collect();
+    println!("Aggregated {} radio cells into {} sites", sites.iter().map(|s| s.cells.len()).sum::<usize>(), sites.len());
+    sites
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lte_cell(cell_id: u64, lat: f64, lon: f64) -> RadioCell {
+        RadioCell {
+            lat,
+            lon,
+            id: CellGlobalId { radio: Radio::LTE, mcc: 262, mnc: 2, area_code: 1, cell_id },
+            range: 1000.0,
+        }
+    }
+
+    #[test]
+    fn test_group_by_enodeb() {
+        let cells = vec![lte_cell(256 + 1, 52.5, 13.4), lte_cell(256 + 2, 52.6, 13.4), lte_cell(512 + 1, 52.5, 13.4)];
+        let sites = group_cells_into_sites(&cells, SiteAggregation::ENodeB);
+        assert_eq!(sites.len(), 2);
+        assert_eq!(sites[0].cells.len(), 2);
+        assert_eq!(sites[1].cells[0].cell_id, 513);
+    }
+
+    #[test]
+    fn test_group_by_location() {
+        let cells = vec![lte_cell(1, 52.5, 13.4), lte_cell(2, 52.50001, 13.4), lte_cell(3, 52.6, 13.4)];
+        let sites = group_cells_into_sites(&cells, SiteAggregation::Location { tolerance_in_meters: 10.0 });
+        assert_eq!(sites.len(), 2);
+        assert_eq!(sites[0].cells.len(), 2);
+        assert!((sites[0].lat - 52.500005).abs() < 1e-9);
+    }
+}
@@ -2,7 +2,9 @@ use serde_with::DurationMilliSeconds;
 use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::{fs, time};
 use std::time::Duration;
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use simulation_curator::attachment_intervals::{compute_attachment_intervals, write_intervals_to_csv, write_intervals_to_parquet, AttachmentChange};
+use simulation_curator::handover::{HandoverMode, HandoverTiming, InterruptionGap};
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 
@@ -56,6 +58,8 @@ pub struct FixedTopology {
     pub nodes: HashMap<u64, Vec<f64>>,
     pub slots: HashMap<u64, u16>,
     pub children: HashMap<u64, Vec<u64>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -65,12 +69,22 @@ pub struct SimulatedReconnects {
 }
 
 impl SimulatedReconnects {
-    fn from_mobile_device_quadrants(mdq: MobileDeviceQuadrants, runtime: Duration, interval: Duration, num_of_devices_to_rotate: u16) -> Self {
+    fn from_mobile_device_quadrants(mdq: MobileDeviceQuadrants, runtime: Duration, interval: Duration, num_of_devices_to_rotate: u16, timing: &mut HandoverTiming) -> Self {
         Self {
             initial_parents: mdq.get_initial_update(),
-            topology_updates: mdq.get_update_vector(runtime, interval, num_of_devices_to_rotate),
+            topology_updates: mdq.get_update_vector(runtime, interval, num_of_devices_to_rotate, timing),
         }
     }
+
+    /// every event as a mobile node being attached to or detached from a parent at the time of its update
+    fn attachment_changes(&self) -> Vec<AttachmentChange> {
+        self.topology_updates.iter().flat_map(|update| update.events.iter().map(|event| AttachmentChange {
+            timestamp: update.timestamp,
+            parent_id: event.parent_id,
+            child_id: event.child_id,
+            attached: event.action == ISQPEventAction::add,
+        })).collect()
+    }
 }
 
 impl FixedTopology {
@@ -87,6 +101,7 @@ impl FixedTopology {
             nodes,
             slots,
             children,
+            root: None,
         }
     }
 }
@@ -98,6 +113,16 @@ impl From<QuadrantConfig> for FixedTopology {
 }
 
 impl FixedTopology{
+    /// add the coordinator as root node, optionally as parent of all fog nodes
+    fn add_root(&mut self, root_id: u64, (lat, lon): (f64, f64), root_slots: u16, connect_fog_nodes: bool) {
+        let mut fog_nodes: Vec<u64> = self.nodes.keys().copied().collect();
+        fog_nodes.sort();
+        self.nodes.insert(root_id, vec![lon, lat]);
+        self.slots.insert(root_id, root_slots);
+        self.children.insert(root_id, if connect_fog_nodes { fog_nodes } else { vec![] });
+        self.root = Some(root_id);
+    }
+
     fn from(mdq: MobileDeviceQuadrants, subtract: u64) -> Self {
         let mut nodes = HashMap::new();
         let mut slots = HashMap::new();
@@ -111,18 +136,21 @@ impl FixedTopology{
             nodes,
             slots,
             children,
+            root: None,
         }
     }
 }
 
 impl MobileDeviceQuadrants {
-    fn rotate_devices(&mut self, num_devices: u16) -> Vec<ISQPEvent> {
+    /// Move devices to the next quadrant. Returns the events emitted immediately and the events delayed by the handover timing.
+    fn rotate_devices(&mut self, num_devices: u16, timing: &mut HandoverTiming) -> (Vec<ISQPEvent>, Vec<(Duration, ISQPEvent)>) {
         let mut events = vec![];
+        let mut delayed = vec![];
         let mut moving_devices: Vec<Option<(u64, MobileEntry)>> = vec![];
         for (quadrant_id, devices) in self.quadrant_map.iter_mut().rev() {
             //for (quadrant_id, devices) in self.quadrant_map.iter_mut() {
             for mut moving_device in &mut moving_devices {
-                Self::rotate_single_device(&mut events, &mut moving_device, *quadrant_id, devices);
+                Self::rotate_single_device(&mut events, &mut delayed, timing, &mut moving_device, *quadrant_id, devices);
             }
             for _ in 0..num_devices {
                 if let Some(device) = devices.pop_front() {
@@ -132,27 +160,31 @@ impl MobileDeviceQuadrants {
         }
         let mut entry = self.quadrant_map.last_entry().unwrap();
         for mut moving_device in &mut moving_devices {
-            Self::rotate_single_device(&mut events, &mut moving_device, *entry.key(), entry.get_mut());
+            Self::rotate_single_device(&mut events, &mut delayed, timing, &mut moving_device, *entry.key(), entry.get_mut());
         }
-        events
+        (events, delayed)
     }
 
-    fn rotate_single_device(events: &mut Vec<ISQPEvent>, moving_device: &mut Option<(u64, MobileEntry)>, quadrant_id: u64, devices: &mut VecDeque<MobileEntry>) {
+    fn rotate_single_device(events: &mut Vec<ISQPEvent>, delayed: &mut Vec<(Duration, ISQPEvent)>, timing: &mut HandoverTiming, moving_device: &mut Option<(u64, MobileEntry)>, quadrant_id: u64, devices: &mut VecDeque<MobileEntry>) {
         if let Some((old_quadrant, device)) = moving_device.take() {
-            events.push(
-                ISQPEvent {
-                    parent_id: old_quadrant,
-                    child_id: device.device_id,
-                    action: ISQPEventAction::remove,
-                }
-            );
-            events.push(
-                ISQPEvent {
-                    parent_id: quadrant_id,
-                    child_id: device.device_id,
-                    action: ISQPEventAction::add,
-                }
-            );
+            let remove = ISQPEvent {
+                parent_id: old_quadrant,
+                child_id: device.device_id,
+                action: ISQPEventAction::remove,
+            };
+            let add = ISQPEvent {
+                parent_id: quadrant_id,
+                child_id: device.device_id,
+                action: ISQPEventAction::add,
+            };
+            let (first, second) = if timing.adds_first() { (add, remove) } else { (remove, add) };
+            let delay = timing.next_delay();
+            events.push(first);
+            if delay.is_zero() {
+                events.push(second);
+            } else {
+                delayed.push((delay, second));
+            }
             devices.push_back(device);
         }
     }
@@ -181,20 +213,22 @@ impl MobileDeviceQuadrants {
             quadrant_map
         }
     }
-    pub fn get_update_vector(mut self, runtime: Duration, interval: Duration, num_devices_to_rotate: u16) -> Vec<TopologyUpdate> {
-        let mut updates = vec![];
+    pub fn get_update_vector(mut self, runtime: Duration, interval: Duration, num_devices_to_rotate: u16, timing: &mut HandoverTiming) -> Vec<TopologyUpdate> {
+        let mut updates: BTreeMap<Duration, Vec<ISQPEvent>> = BTreeMap::new();
 
         let mut timestamp = Duration::new(0, 0);
 
         //insert reconnects
         while timestamp < runtime {
-            updates.push(TopologyUpdate {
-                timestamp,
-                events: self.rotate_devices(num_devices_to_rotate),
-            });
+            let (events, delayed) = self.rotate_devices(num_devices_to_rotate, timing);
+            updates.entry(timestamp).or_default().extend(events);
+            //delayed events are emitted at the latest with the next update so that the events of a device stay in order
+            for (delay, event) in delayed {
+                updates.entry(timestamp + delay.min(interval)).or_default().push(event);
+            }
             timestamp += interval;
         }
-        updates
+        updates.into_iter().map(|(timestamp, events)| TopologyUpdate { timestamp, events }).collect()
     }
 
     pub fn get_initial_update(&self) -> Vec<(u64, u64)> {
@@ -222,6 +256,7 @@ impl MobileDeviceQuadrants {
 mod tests {
     use std::time::SystemTime;
     use crate::FixedTopology;
+    use simulation_curator::handover::{HandoverMode, HandoverTiming, InterruptionGap};
 
 
     #[test]
@@ -229,7 +264,7 @@ mod tests {
         let mut mdq = super::MobileDeviceQuadrants::populate(4, 3, 1, 100);
         let json = serde_json::to_string_pretty(&mdq).unwrap();
         println!("{}", json);
-        let isqp_events = mdq.rotate_devices(2);
+        let isqp_events = mdq.rotate_devices(2, &mut HandoverTiming::new(HandoverMode::Instant, 0));
         let json = serde_json::to_string_pretty(&isqp_events).unwrap();
         println!("{}", json);
         let json = serde_json::to_string_pretty(&mdq).unwrap();
@@ -246,7 +281,7 @@ mod tests {
         let json = serde_json::to_string_pretty(&source_groups).unwrap();
         println!("{}", json);
         let _ = serde_json::to_string_pretty(&mdq).unwrap();
-        let simulated_reconnects = super::SimulatedReconnects::from_mobile_device_quadrants(mdq, std::time::Duration::new(6, 0), std::time::Duration::new(2, 0), 2);
+        let simulated_reconnects = super::SimulatedReconnects::from_mobile_device_quadrants(mdq, std::time::Duration::new(6, 0), std::time::Duration::new(2, 0), 2, &mut HandoverTiming::new(HandoverMode::Instant, 0));
         let json = serde_json::to_string_pretty(&simulated_reconnects).unwrap();
         println!("{}", json);
     }
@@ -256,11 +291,36 @@ mod tests {
         let mut mdq = super::MobileDeviceQuadrants::populate(4, 4, 1, 100);
         let json = serde_json::to_string_pretty(&mdq).unwrap();
         println!("{}", json);
-        let isqp_events = mdq.get_update_vector(std::time::Duration::new(6, 0), std::time::Duration::new(2, 0), 2);
+        let isqp_events = mdq.get_update_vector(std::time::Duration::new(6, 0), std::time::Duration::new(2, 0), 2, &mut HandoverTiming::new(HandoverMode::Instant, 0));
         let json = serde_json::to_string_pretty(&isqp_events).unwrap();
         println!("{}", json);
     }
 
+    #[test]
+    fn test_break_before_make() {
+        let mdq = super::MobileDeviceQuadrants::populate(4, 4, 1, 100);
+        let mut timing = HandoverTiming::new(HandoverMode::BreakBeforeMake { gap: InterruptionGap::Fixed(std::time::Duration::from_millis(500)) }, 0);
+        let updates = mdq.get_update_vector(std::time::Duration::new(6, 0), std::time::Duration::new(2, 0), 2, &mut timing);
+        assert_eq!(updates.len(), 6);
+        assert!(updates[0].events.iter().all(|e| e.action == super::ISQPEventAction::remove));
+        assert_eq!(updates[1].timestamp, std::time::Duration::from_millis(500));
+        assert!(updates[1].events.iter().all(|e| e.action == super::ISQPEventAction::add));
+        assert_eq!(updates[0].events.len(), updates[1].events.len());
+    }
+
+    #[test]
+    fn test_attachment_intervals() {
+        let mdq = super::MobileDeviceQuadrants::populate(2, 1, 2, 100);
+        let mut timing = HandoverTiming::new(HandoverMode::BreakBeforeMake { gap: InterruptionGap::Fixed(std::time::Duration::from_millis(500)) }, 0);
+        let reconnects = super::SimulatedReconnects::from_mobile_device_quadrants(mdq, std::time::Duration::new(2, 0), std::time::Duration::new(2, 0), 1, &mut timing);
+        let changes = reconnects.attachment_changes();
+        assert_eq!(changes.len(), reconnects.topology_updates.iter().map(|u| u.events.len()).sum::<usize>());
+        let intervals = super::compute_attachment_intervals(&reconnects.initial_parents, changes, std::time::Duration::new(2, 0), std::time::Duration::ZERO, |t| t);
+        //every device is detached from its first quadrant at 0s and attached to the next one after the gap
+        let first = intervals.iter().find(|i| i.child_id == 100 && i.emulated_start > std::time::Duration::ZERO).unwrap();
+        assert_eq!((first.emulated_start, first.real_start_time.as_str()), (std::time::Duration::from_millis(500), "00:00:00"));
+    }
+
     #[test]
     fn test_time() {
         let now = SystemTime::now();
@@ -271,6 +331,16 @@ mod tests {
     }
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum HandoverArg {
+    /// Remove the old and add the new link in the same update
+    Instant,
+    /// Add the new link first and remove the old link after the overlap
+    MakeBeforeBreak,
+    /// Remove the old link first and add the new link after the interruption gap
+    BreakBeforeMake,
+}
+
 /// Program to generate synthetic topology change events
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -290,6 +360,47 @@ struct Args {
     /// Number of moving devices per topology update
     #[arg(long, default_value_t = 1)]
     moving_devices: u16,
+
+    /// Write the coordinator as root node into fixed_topology.json. Fog nodes are then numbered starting after the root id
+    /// instead of being shifted to start at 1.
+    #[arg(long, default_value_t = false)]
+    include_root: bool,
+
+    /// The id of the root node.
+    #[arg(long, default_value_t = 1, requires = "include_root")]
+    root_id: u64,
+
+    /// Comma separated latitude and longitude of the root node. Defaults to the location of the fog nodes (0,0).
+    #[arg(long, value_parser = simulation_curator::nes_simulation::parse_location, requires = "include_root")]
+    root_location: Option<(f64, f64)>,
+
+    /// The number of slots of the root node.
+    #[arg(long, default_value_t = u16::MAX, requires = "include_root")]
+    root_slots: u16,
+
+    /// Connect all fog nodes to the root node
+    #[arg(long, default_value_t = false, requires = "include_root")]
+    root_to_fog_edges: bool,
+
+    /// Order and timing of the remove and add event of a handover
+    #[arg(long, value_enum, default_value_t = HandoverArg::Instant)]
+    handover: HandoverArg,
+
+    /// Time in milliseconds during which a device is connected to the old and the new fog node in make-before-break handovers
+    #[arg(long, default_value_t = 0)]
+    handover_overlap_in_ms: u64,
+
+    /// Time in milliseconds during which a device is disconnected in break-before-make handovers
+    #[arg(long, default_value_t = 0)]
+    handover_gap_in_ms: u64,
+
+    /// If set, the interruption gap of each break-before-make handover is drawn uniformly between the handover gap and this value
+    #[arg(long, default_value = None)]
+    handover_max_gap_in_ms: Option<u64>,
+
+    /// Seed of the random number generator used for random handover gaps
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
 }
 
 fn main() {
@@ -297,7 +408,8 @@ fn main() {
     
     let output_path = args.output_path;
     let quadrants = args.fog_nodes;
-    let quadrant_start_id = 2;
+    //the coordinator has the id before the first fog node
+    let quadrant_start_id = if args.include_root { args.root_id + 1 } else { 2 };
     let mobile_devices_per_quadrant = args.mobile_devices_per_fog_node;
     let num_of_devices_to_rotate = args.moving_devices;
     let mdq = MobileDeviceQuadrants::populate(
@@ -309,22 +421,45 @@ fn main() {
 
     /*subtract 1 from id because the runner script expects the ids to start at 1
      * but the reconnects are generated with the ids starting at 2 (coordinator has 1 as id)
+     * if the root is part of the topology, the ids do not need to be shifted and start after the root id,
+     * so the source groups (the fog node ids) differ from a run without the root
      */
-    let topology = FixedTopology::from(mdq.clone(), 1);
+    let subtract = if args.include_root { 0 } else { 1 };
+    let mut topology = FixedTopology::from(mdq.clone(), subtract);
+    if args.include_root {
+        topology.add_root(args.root_id, args.root_location.unwrap_or((0.0, 0.0)), args.root_slots, args.root_to_fog_edges);
+    }
     let json = serde_json::to_string_pretty(&topology).unwrap();
     let topology_output_path = format!("{}/fixed_topology.json", output_path);
     fs::write(topology_output_path, json).unwrap();
 
 
-    let source_groups = mdq.compute_source_groups(1);
+    let source_groups = mdq.compute_source_groups(subtract);
     let json = serde_json::to_string_pretty(&source_groups).unwrap();
     let source_groups_output_path = format!("{}/source_groups.json", output_path);
     fs::write(source_groups_output_path, json).unwrap();
 
     let runtime = std::time::Duration::new(120, 0);
     let interval = std::time::Duration::from_millis(1000);
-    let simulated_reconnects = SimulatedReconnects::from_mobile_device_quadrants(mdq, runtime, interval, num_of_devices_to_rotate);
+    let handover_mode = match args.handover {
+        HandoverArg::Instant => HandoverMode::Instant,
+        HandoverArg::MakeBeforeBreak => HandoverMode::MakeBeforeBreak { overlap: Duration::from_millis(args.handover_overlap_in_ms) },
+        HandoverArg::BreakBeforeMake => {
+            let gap = match args.handover_max_gap_in_ms {
+                Some(max) => InterruptionGap::Uniform { min: Duration::from_millis(args.handover_gap_in_ms), max: Duration::from_millis(max) },
+                None => InterruptionGap::Fixed(Duration::from_millis(args.handover_gap_in_ms)),
+            };
+            HandoverMode::BreakBeforeMake { gap }
+        }
+    };
+    let mut timing = HandoverTiming::new(handover_mode, args.seed);
+    let simulated_reconnects = SimulatedReconnects::from_mobile_device_quadrants(mdq, runtime, interval, num_of_devices_to_rotate, &mut timing);
     let json = serde_json::to_string_pretty(&simulated_reconnects).unwrap();
     let simulated_reconnects_output_path = format!("{}/topology_updates.json", output_path);
     fs::write(simulated_reconnects_output_path, json).unwrap();
+
+    //the synthetic updates are not compressed, real and emulated time are the same
+    let intervals = compute_attachment_intervals(&simulated_reconnects.initial_parents, simulated_reconnects.attachment_changes(), runtime, Duration::ZERO, |t| t);
+    write_intervals_to_csv(&intervals, &format!("{}/attachment_intervals.csv", output_path)).unwrap();
+    write_intervals_to_parquet(&intervals, &format!("{}/attachment_intervals.parquet", output_path)).unwrap();
 }
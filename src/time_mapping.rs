@@ -0,0 +1,148 @@
+use std::fs;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use serde_with::DurationMilliSeconds;
+use crate::gtfs::duration_to_string;
+
+/// A point of the mapping from the offset in the schedule to the offset in the emulation
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TimeMappingPoint {
+    /// Time of day in the schedule
+    pub real_time: String,
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    pub real_offset: Duration,
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    pub emulated_offset: Duration,
+}
+
+/// Piecewise linear mapping from the real time of the schedule to the emulated time of the topology updates
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TimeMapping {
+    pub points: Vec<TimeMappingPoint>,
+}
+
+impl TimeMapping {
+    fn from_offsets(start_time: Duration, offsets: &[(Duration, Duration)]) -> Self {
+        let points = offsets.iter().map(|(real_offset, emulated_offset)| TimeMappingPoint {
+            real_time: duration_to_string(start_time + *real_offset),
+            real_offset: *real_offset,
+            emulated_offset: *emulated_offset,
+        }).collect();
+        TimeMapping { points }
+    }
+
+    /// Compress the whole schedule by the same factor
+    pub fn from_speedup(start_time: Duration, real_duration: Duration, speedup: f64) -> Self {
+        Self::from_offsets(start_time, &[(Duration::ZERO, Duration::ZERO), (real_duration, real_duration.div_f64(speedup))])
+    }
+
+    /// Compress the whole schedule uniformly into the target runtime
+    pub fn uniform(start_time: Duration, real_duration: Duration, target_runtime: Duration) -> Self {
+        Self::from_offsets(start_time, &[(Duration::ZERO, Duration::ZERO), (real_duration, target_runtime)])
+    }
+
+    /// Compress the schedule into the target runtime, giving each segment a share of the runtime that blends its length with its
+    /// share of the events. With an activity weight of 0 the compression is uniform, with a weight of 1 quiet segments are skipped entirely.
+    pub fn activity_weighted(start_time: Duration, real_duration: Duration, target_runtime: Duration, event_offsets: &[Duration], segment_length: Duration, activity_weight: f64) -> Self {
+        let segment_count = (real_duration.as_secs_f64() / segment_length.as_secs_f64()).ceil().max(1.0) as usize;
+        let mut events_per_segment = vec![0usize; segment_count];
+        for offset in event_offsets {
+            let segment = ((offset.as_secs_f64() / segment_length.as_secs_f64()) as usize).min(segment_count - 1);
+            events_per_segment[segment] += 1;
+        }
+        let total_events = events_per_segment.iter().sum::<usize>();
+
+        let mut offsets = vec![(Duration::ZERO, Duration::ZERO)];
+        let mut emulated_offset = 0.0;
+        for (i, events) in events_per_segment.iter().enumerate() {
+            let real_end = (segment_length * (i as u32 + 1)).min(real_duration);
+            let real_length = real_end - offsets.last().unwrap().0;
+            let time_share = real_length.as_secs_f64() / real_duration.as_secs_f64();
+            let share = if total_events == 0 {
+                time_share
+            } else {
+                (1.0 - activity_weight) * time_share + activity_weight * *events as f64 / total_events as f64
+            };
+            emulated_offset += share * target_runtime.as_secs_f64();
+            offsets.push((real_end, Duration::from_secs_f64(emulated_offset)));
+        }
+        Self::from_offsets(start_time, &offsets)
+    }
+
+    /// The emulated offset of a real offset. Offsets after the last point are mapped to the end of the emulation.
+    pub fn map(&self, real_offset: Duration) -> Duration {
+        for window in self.points.windows(2) {
+            let (from, to) = (&window[0], &window[1]);
+            if real_offset <= to.real_offset {
+                if to.real_offset == from.real_offset {
+                    return to.emulated_offset;
+                }
+                let fraction = (real_offset.saturating_sub(from.real_offset)).as_secs_f64() / (to.real_offset - from.real_offset).as_secs_f64();
+                return from.emulated_offset + (to.emulated_offset - from.emulated_offset).mul_f64(fraction);
+            }
+        }
+        self.points.last().unwrap().emulated_offset
+    }
+
+    /// The real offset of an emulated offset. Real periods that are skipped in the emulation are mapped to their start.
+    pub fn unmap(&self, emulated_offset: Duration) -> Duration {
+        for window in self.points.windows(2) {
+            let (from, to) = (&window[0], &window[1]);
+            if emulated_offset <= to.emulated_offset {
+                if to.emulated_offset == from.emulated_offset {
+                    return from.real_offset;
+                }
+                let fraction = (emulated_offset.saturating_sub(from.emulated_offset)).as_secs_f64() / (to.emulated_offset - from.emulated_offset).as_secs_f64();
+                return from.real_offset + (to.real_offset - from.real_offset).mul_f64(fraction);
+            }
+        }
+        self.points.last().unwrap().real_offset
+    }
+
+    pub fn print_summary(&self) {
+        for window in self.points.windows(2) {
+            let (from, to) = (&window[0], &window[1]);
+            let emulated = (to.emulated_offset - from.emulated_offset).as_secs_f64();
+            let speedup = if emulated > 0.0 { format!("{:.1}x", (to.real_offset - from.real_offset).as_secs_f64() / emulated) } else { "skipped".to_string() };
+            println!("{} - {} is emulated from {:.1}s to {:.1}s ({})", from.real_time, to.real_time, from.emulated_offset.as_secs_f64(), to.emulated_offset.as_secs_f64(), speedup);
+        }
+    }
+
+    pub fn write_to_file(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let json_string = serde_json::to_string_pretty(self)?;
+        Ok(fs::write(path, json_string)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_activity_weighted_mapping() {
+        let events = vec![Duration::from_secs(70), Duration::from_secs(80), Duration::from_secs(90)];
+        let mapping = TimeMapping::activity_weighted(Duration::from_secs(8 * 3600), Duration::from_secs(120), Duration::from_secs(10), &events, Duration::from_secs(60), 1.0);
+        assert_eq!(mapping.points.len(), 3);
+        assert_eq!(mapping.map(Duration::from_secs(60)), Duration::ZERO);
+        assert_eq!(mapping.map(Duration::from_secs(90)), Duration::from_secs(5));
+        assert_eq!(mapping.map(Duration::from_secs(500)), Duration::from_secs(10));
+        assert_eq!(mapping.points[1].real_time, "08:01:00");
+    }
+
+    #[test]
+    fn test_unmap() {
+        let uniform = TimeMapping::uniform(Duration::ZERO, Duration::from_secs(100), Duration::from_secs(10));
+        assert_eq!(uniform.unmap(uniform.map(Duration::from_secs(30))), Duration::from_secs(30));
+        assert_eq!(uniform.unmap(Duration::from_secs(20)), Duration::from_secs(100));
+
+        //the minute without events between 60s and 120s is skipped
+        let events = vec![Duration::from_secs(30), Duration::from_secs(150)];
+        let mapping = TimeMapping::activity_weighted(Duration::ZERO, Duration::from_secs(180), Duration::from_secs(10), &events, Duration::from_secs(60), 1.0);
+        assert_eq!(mapping.map(Duration::from_secs(60)), mapping.map(Duration::from_secs(120)));
+        assert_eq!(mapping.unmap(Duration::from_secs(5)), Duration::from_secs(60));
+        assert_eq!(mapping.unmap(Duration::from_millis(7500)), Duration::from_secs(150));
+        assert_eq!(mapping.unmap(Duration::from_millis(2500)), Duration::from_secs(30));
+    }
+}
@@ -0,0 +1,61 @@
+use std::time::Duration;
+use clap::{Parser, ValueEnum};
+use simulation_curator::nes_simulation::{FixedTopology, SimulatedReconnects};
+use simulation_curator::topology_state::TopologyStateIndex;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum SnapshotFormat {
+    /// The children of every node
+    Json,
+    /// Fixed nodes, the edges between them and the mobile nodes drawn at their parent
+    Geojson,
+}
+
+/// Program to export the topology at a point in time of the topology updates
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Path to the fixed topology
+    #[arg(long, default_value = "fixed_topology.json")]
+    fixed_topology_path: String,
+
+    /// Path to the topology updates
+    #[arg(long, default_value = "topology_updates.json")]
+    topology_updates_path: String,
+
+    /// The emulated time in milliseconds at which the topology is exported. Updates with exactly this timestamp are applied.
+    #[arg(long)]
+    time_in_ms: u64,
+
+    /// Print the parents of this node at the given time
+    #[arg(long)]
+    child_id: Option<u64>,
+
+    /// Format of the exported snapshot
+    #[arg(long, value_enum, default_value_t = SnapshotFormat::Json)]
+    format: SnapshotFormat,
+
+    /// Path to the file where the snapshot will be produced
+    #[arg(long, default_value = "topology_snapshot.json")]
+    output_path: String,
+}
+
+fn main() {
+    let args = Args::parse();
+    let topology = FixedTopology::read_from_file(&args.fixed_topology_path).unwrap();
+    let reconnects = SimulatedReconnects::read_from_file(&args.topology_updates_path).unwrap();
+    let index = TopologyStateIndex::new(&topology, &reconnects);
+    let time = Duration::from_millis(args.time_in_ms);
+
+    if let Some(child_id) = args.child_id {
+        println!("Parents of node {} at {}ms: {:?}", child_id, args.time_in_ms, index.parent_of(child_id, time));
+    }
+
+    let snapshot = index.snapshot(time);
+    println!("Topology at {}ms contains {} edges", args.time_in_ms, snapshot.children.values().map(|c| c.len()).sum::<usize>());
+    match args.format {
+        SnapshotFormat::Json => snapshot.write_to_file(&args.output_path).unwrap(),
+        SnapshotFormat::Geojson => std::fs::write(&args.output_path, snapshot.to_geojson(&topology).to_string()).unwrap(),
+    }
+    println!("Wrote snapshot to {}", args.output_path);
+}
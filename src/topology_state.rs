@@ -0,0 +1,229 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::time::Duration;
+use geojson::{Feature, FeatureCollection, GeoJson, Geometry, Value};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use serde_with::DurationMilliSeconds;
+use crate::nes_simulation::{FixedTopology, ISQPEventAction, SimulatedReconnects};
+
+const DEFAULT_CHECKPOINT_INTERVAL: usize = 64;
+
+// an edge that is added or removed by a topology update
+#[derive(Clone, Copy, Debug)]
+struct EdgeChange {
+    parent_id: u64,
+    child_id: u64,
+    added: bool,
+}
+
+// all edges of the topology, indexed in both directions
+#[derive(Clone, Debug, Default)]
+struct TopologyState {
+    parents: HashMap<u64, BTreeSet<u64>>,
+    children: HashMap<u64, BTreeSet<u64>>,
+}
+
+impl TopologyState {
+    fn apply(&mut self, change: &EdgeChange) {
+        if change.added {
+            self.parents.entry(change.child_id).or_default().insert(change.parent_id);
+            self.children.entry(change.parent_id).or_default().insert(change.child_id);
+        } else {
+            if let Some(parents) = self.parents.get_mut(&change.child_id) {
+                parents.remove(&change.parent_id);
+            }
+            if let Some(children) = self.children.get_mut(&change.parent_id) {
+                children.remove(&change.child_id);
+            }
+        }
+    }
+}
+
+/// Answers which edges exist at a point in time of the topology updates. The full state is stored every few updates
+/// and the state in between is reconstructed by replaying the updates following the closest earlier checkpoint.
+pub struct TopologyStateIndex {
+    timestamps: Vec<Duration>,
+    changes: Vec<Vec<EdgeChange>>,
+    //checkpoints[i] is the state after applying the first i * checkpoint_interval updates
+    checkpoints: Vec<TopologyState>,
+    checkpoint_interval: usize,
+}
+
+/// The edges of the topology at a point in time
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TopologySnapshot {
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    pub timestamp: Duration,
+    pub children: BTreeMap<u64, Vec<u64>>,
+}
+
+impl TopologyStateIndex {
+    pub fn new(topology: &FixedTopology, reconnects: &SimulatedReconnects) -> Self {
+        Self::with_checkpoint_interval(topology, reconnects, DEFAULT_CHECKPOINT_INTERVAL)
+    }
+
+    /// Store the full state every checkpoint_interval updates. An interval of 0 is treated as 1, a checkpoint after every update.
+    pub fn with_checkpoint_interval(topology: &FixedTopology, reconnects: &SimulatedReconnects, checkpoint_interval: usize) -> Self {
+        let checkpoint_interval = checkpoint_interval.max(1);
+        let mut state = TopologyState::default();
+        for (parent_id, children) in &topology.children {
+            for child_id in children {
+                state.apply(&EdgeChange { parent_id: *parent_id, child_id: *child_id, added: true });
+            }
+        }
+        for (parent_id, child_id) in reconnects.initial_parents() {
+            state.apply(&EdgeChange { parent_id: *parent_id, child_id: *child_id, added: true });
+        }
+
+        let mut timestamps = vec![];
+        let mut changes = vec![];
+        let mut checkpoints = vec![state.clone()];
+        for update in &reconnects.topology_updates {
+            let update_changes: Vec<EdgeChange> = update.events.iter().map(|event| EdgeChange {
+                parent_id: event.parent_id,
+                child_id: event.child_id,
+                added: event.action == ISQPEventAction::add,
+            }).collect();
+            update_changes.iter().for_each(|change| state.apply(change));
+            timestamps.push(update.timestamp);
+            changes.push(update_changes);
+            if changes.len() % checkpoint_interval == 0 {
+                checkpoints.push(state.clone());
+            }
+        }
+        TopologyStateIndex { timestamps, changes, checkpoints, checkpoint_interval }
+    }
+
+    // the checkpoint preceding the time and the changes applied after it up to and including the time
+    fn checkpoint_and_changes(&self, time: Duration) -> (&TopologyState, impl Iterator<Item=&EdgeChange>) {
+        let applied = self.timestamps.partition_point(|timestamp| *timestamp <= time);
+        let checkpoint = applied / self.checkpoint_interval;
+        let changes = self.changes[checkpoint * self.checkpoint_interval..applied].iter().flatten();
+        (&self.checkpoints[checkpoint], changes)
+    }
+
+    /// The parents of a node at the given time. Mobile nodes may have several parents during a make-before-break handover.
+    pub fn parent_of(&self, child_id: u64, time: Duration) -> Vec<u64> {
+        let (checkpoint, changes) = self.checkpoint_and_changes(time);
+        let mut parents = checkpoint.parents.get(&child_id).cloned().unwrap_or_default();
+        for change in changes.filter(|c| c.child_id == child_id) {
+            if change.added {
+                parents.insert(change.parent_id);
+            } else {
+                parents.remove(&change.parent_id);
+            }
+        }
+        parents.into_iter().collect()
+    }
+
+    /// The children of a node at the given time
+    pub fn children_of(&self, parent_id: u64, time: Duration) -> Vec<u64> {
+        let (checkpoint, changes) = self.checkpoint_and_changes(time);
+        let mut children = checkpoint.children.get(&parent_id).cloned().unwrap_or_default();
+        for change in changes.filter(|c| c.parent_id == parent_id) {
+            if change.added {
+                children.insert(change.child_id);
+            } else {
+                children.remove(&change.child_id);
+            }
+        }
+        children.into_iter().collect()
+    }
+
+    /// All edges of the topology at the given time
+    pub fn snapshot(&self, time: Duration) -> TopologySnapshot {
+        let (checkpoint, changes) = self.checkpoint_and_changes(time);
+        let mut state = checkpoint.clone();
+        changes.for_each(|change| state.apply(change));
+        let children = state.children.into_iter()
+            .filter(|(_, children)| !children.is_empty())
+            .map(|(parent_id, children)| (parent_id, children.into_iter().collect()))
+            .collect();
+        TopologySnapshot { timestamp: time, children }
+    }
+}
+
+impl TopologySnapshot {
+    pub fn write_to_file(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let json_string = serde_json::to_string_pretty(self)?;
+        Ok(fs::write(path, json_string)?)
+    }
+
+    /// Draw the fixed nodes and the edges between them. Mobile nodes have no location and are drawn at the location of their parent.
+    pub fn to_geojson(&self, topology: &FixedTopology) -> GeoJson {
+        let mut features = vec![];
+        let point_feature = |geometry: Value, properties: geojson::JsonObject| Feature {
+            bbox: None,
+            geometry: Some(Geometry::new(geometry)),
+            id: None,
+            properties: Some(properties),
+            foreign_members: None,
+        };
+
+        let mut node_ids: Vec<&u64> = topology.nodes.keys().collect();
+        node_ids.sort();
+        for node_id in node_ids {
+            let mut properties = geojson::JsonObject::new();
+            properties.insert("node_id".to_string(), (*node_id).into());
+            properties.insert("marker-color".to_string(), "#3F51B5".into());
+            features.push(point_feature(Value::Point(topology.nodes[node_id].clone()), properties));
+        }
+
+        for (parent_id, children) in &self.children {
+            let Some(parent) = topology.nodes.get(parent_id) else {
+                continue;
+            };
+            for child_id in children {
+                let mut properties = geojson::JsonObject::new();
+                properties.insert("parent_id".to_string(), (*parent_id).into());
+                properties.insert("child_id".to_string(), (*child_id).into());
+                match topology.nodes.get(child_id) {
+                    Some(child) => {
+                        properties.insert("stroke".to_string(), "#3F51B5".into());
+                        features.push(point_feature(Value::LineString(vec![parent.clone(), child.clone()]), properties));
+                    }
+                    None => {
+                        properties.insert("node_id".to_string(), (*child_id).into());
+                        properties.insert("marker-color".to_string(), "#673AB7".into());
+                        properties.insert("marker-size".to_string(), "small".into());
+                        features.push(point_feature(Value::Point(parent.clone()), properties));
+                    }
+                }
+            }
+        }
+
+        GeoJson::FeatureCollection(FeatureCollection {
+            bbox: None,
+            features,
+            foreign_members: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_index() {
+        let topology: FixedTopology = serde_json::from_str(r#"{"nodes": {"2": [13.4, 52.5], "3": [13.5, 52.5]}, "slots": {"2": 1, "3": 1}, "children": {"2": [], "3": []}}"#).unwrap();
+        let reconnects: SimulatedReconnects = serde_json::from_str(r#"{"initial_parents": [[2, 10]], "topology_updates": [
+            {"timestamp": 1000, "events": [{"parentId": 2, "childId": 10, "action": "remove"}, {"parentId": 3, "childId": 10, "action": "add"}]},
+            {"timestamp": 2000, "events": [{"parentId": 3, "childId": 10, "action": "remove"}, {"parentId": 2, "childId": 10, "action": "add"}]},
+            {"timestamp": 3000, "events": [{"parentId": 2, "childId": 10, "action": "remove"}, {"parentId": 3, "childId": 10, "action": "add"}]}]}"#).unwrap();
+        let index = TopologyStateIndex::with_checkpoint_interval(&topology, &reconnects, 2);
+        assert_eq!(index.parent_of(10, Duration::from_millis(999)), vec![2]);
+        assert_eq!(index.parent_of(10, Duration::from_millis(1000)), vec![3]);
+        assert_eq!(index.children_of(2, Duration::from_millis(2500)), vec![10]);
+        assert_eq!(index.parent_of(10, Duration::from_secs(10)), vec![3]);
+        let snapshot = index.snapshot(Duration::from_millis(2000));
+        assert_eq!(snapshot.children, BTreeMap::from([(2, vec![10])]));
+
+        //without a checkpoint interval the state is stored after every update
+        let index = TopologyStateIndex::with_checkpoint_interval(&topology, &reconnects, 0);
+        assert_eq!(index.checkpoints.len(), 4);
+        assert_eq!(index.parent_of(10, Duration::from_millis(2500)), vec![2]);
+    }
+}
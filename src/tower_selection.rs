@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use crate::cell_data::{find_closest_towers, CellGlobalId, MultiTripAndCellData, RadioCell};
+use crate::geo_utils;
+
+/// Strategy used to reduce the set of radio cells to a fixed number of fog nodes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TowerSelection {
+    /// Cluster the tower locations with k-means and keep the tower closest to each centroid
+    KMeans,
+    /// Cluster the tower locations around k towers minimizing the distance to the other towers of their cluster
+    KMedoids,
+    /// Greedily pick the towers covering most of the not yet covered shape points within their range
+    GreedyCoverage,
+}
+
+const MAX_ITERATIONS: usize = 100;
+
+fn distance((lat1, lon1): (f64, f64), (lat2, lon2): (f64, f64)) -> f64 {
+    geo_utils::vincenty_dist_between_coordinates((lat1, lon1), (lat2, lon2))
+}
+
+fn index_of_closest(candidates: &[(f64, f64)], location: (f64, f64)) -> usize {
+    let mut closest = 0;
+    let mut min_distance = f64::MAX;
+    for (i, candidate) in candidates.iter().enumerate() {
+        let d = distance(location, *candidate);
+        if d < min_distance {
+            min_distance = d;
+            closest = i;
+        }
+    }
+    closest
+}
+
+// deterministic initialisation: start with the first location and repeatedly add the location farthest away from all chosen ones.
+// Once only locations at a distance of 0 are left (co-located cells), they are added in order so that every seed is a different location index
+fn farthest_point_seeds(locations: &[(f64, f64)], k: usize) -> Vec<usize> {
+    let mut seeds = vec![0];
+    let mut min_distances: Vec<f64> = locations.iter().map(|l| distance(*l, locations[0])).collect();
+    while seeds.len() < k {
+        let next = (0..locations.len())
+            .filter(|i| !seeds.contains(i))
+            .max_by(|a, b| min_distances[*a].total_cmp(&min_distances[*b]).then(b.cmp(a)));
+        let Some(next) = next else {
+            break;
+        };
+        seeds.push(next);
+        for (i, location) in locations.iter().enumerate() {
+            min_distances[i] = min_distances[i].min(distance(*location, locations[next]));
+        }
+    }
+    seeds
+}
+
+fn k_means(locations: &[(f64, f64)], k: usize) -> Vec<usize> {
+    let mut centroids: Vec<(f64, f64)> = farthest_point_seeds(locations, k).iter().map(|i| locations[*i]).collect();
+    let mut assignment = vec![usize::MAX; locations.len()];
+    for _ in 0..MAX_ITERATIONS {
+        let new_assignment: Vec<usize> = locations.iter().map(|l| index_of_closest(&centroids, *l)).collect();
+        if new_assignment == assignment {
+            break;
+        }
+        assignment = new_assignment;
+        for (c, centroid) in centroids.iter_mut().enumerate() {
+            let members: Vec<&(f64, f64)> = locations.iter().zip(&assignment).filter(|(_, a)| **a == c).map(|(l, _)| l).collect();
+            if !members.is_empty() {
+                let lat = members.iter().map(|l| l.0).sum::<f64>() / members.len() as f64;
+                let lon = members.iter().map(|l| l.1).sum::<f64>() / members.len() as f64;
+                *centroid = (lat, lon);
+            }
+        }
+    }
+
+    //replace every centroid with the location closest to it
+    let mut selected = vec![];
+    for centroid in centroids {
+        let closest = (0..locations.len())
+            .filter(|i| !selected.contains(i))
+            .min_by(|a, b| distance(locations[*a], centroid).total_cmp(&distance(locations[*b], centroid)));
+        if let Some(closest) = closest {
+            selected.push(closest);
+        }
+    }
+    selected
+}
+
+fn k_medoids(locations: &[(f64, f64)], k: usize) -> Vec<usize> {
+    let mut medoids = farthest_point_seeds(locations, k);
+    for _ in 0..MAX_ITERATIONS {
+        let medoid_locations: Vec<(f64, f64)> = medoids.iter().map(|i| locations[*i]).collect();
+        let assignment: Vec<usize> = locations.iter().map(|l| index_of_closest(&medoid_locations, *l)).collect();
+        let mut new_medoids = vec![];
+        for (c, medoid) in medoids.iter().enumerate() {
+            let members: Vec<usize> = (0..locations.len()).filter(|i| assignment[*i] == c).collect();
+            let cost = |candidate: usize| members.iter().map(|m| distance(locations[*m], locations[candidate])).sum::<f64>();
+            let best = members.iter().copied().min_by(|a, b| cost(*a).total_cmp(&cost(*b))).unwrap_or(*medoid);
+            new_medoids.push(best);
+        }
+        if new_medoids == medoids {
+            break;
+        }
+        medoids = new_medoids;
+    }
+    medoids
+}
+
+/// Select k representatives of a set of (lat, lon) locations with k-medoids clustering. Returns the indices of the selected locations.
+pub fn select_medoid_locations(locations: &[(f64, f64)], k: usize) -> Vec<usize> {
+    if locations.len() <= k {
+        return (0..locations.len()).collect();
+    }
+    k_medoids(locations, k)
+}
+
+fn greedy_coverage(towers: &[&RadioCell], demand: &[(f64, f64)], k: usize) -> Vec<CellGlobalId> {
+    let distances: Vec<Vec<f64>> = towers.iter().map(|t| demand.iter().map(|p| distance((t.lat, t.lon), *p)).collect()).collect();
+    let mut covered = vec![false; demand.len()];
+    //start with the largest distance so that the first tower is chosen by its overall proximity to the shape points
+    let max_distance = distances.iter().flatten().copied().fold(0.0, f64::max);
+    let mut nearest_selected = vec![max_distance; demand.len()];
+    let mut selected: Vec<usize> = vec![];
+    while selected.len() < k {
+        let newly_covered = |t: usize| (0..demand.len()).filter(|p| !covered[*p] && distances[t][*p] <= towers[t].range).count();
+        //once every reachable point is covered, pick the tower reducing the distance to the shape points the most
+        let distance_gain = |t: usize| (0..demand.len()).map(|p| (nearest_selected[p] - distances[t][p]).max(0.0)).sum::<f64>();
+        let best = (0..towers.len())
+            .filter(|t| !selected.contains(t))
+            .max_by(|a, b| newly_covered(*a).cmp(&newly_covered(*b)).then(distance_gain(*a).total_cmp(&distance_gain(*b))));
+        let Some(best) = best else {
+            break;
+        };
+        for p in 0..demand.len() {
+            if distances[best][p] <= towers[best].range {
+                covered[p] = true;
+            }
+            nearest_selected[p] = nearest_selected[p].min(distances[best][p]);
+        }
+        selected.push(best);
+    }
+    println!("Selected towers cover {} of {} shape points", covered.iter().filter(|c| **c).count(), demand.len());
+    selected.iter().map(|i| towers[*i].id).collect()
+}
+
+/// Reduce the radio cells to the target number of towers and re-associate every shape point with the closest remaining tower
+pub fn select_towers(cell_data: &mut MultiTripAndCellData, target_count: usize, selection: TowerSelection) {
+    if target_count == 0 || cell_data.radio_cells.len() <= target_count {
+        println!("Keeping all {} radio cells, target count is {}", cell_data.radio_cells.len(), target_count);
+        return;
+    }
+
+    let mut towers: Vec<&RadioCell> = cell_data.radio_cells.values().collect();
+    towers.sort_by_key(|cell| (cell.id.cell_id, cell.id));
+
+    let locations: Vec<(f64, f64)> = towers.iter().map(|t| (t.lat, t.lon)).collect();
+    let selected = match selection {
+        TowerSelection::KMeans => k_means(&locations, target_count).iter().map(|i| towers[*i].id).collect(),
+        TowerSelection::KMedoids => k_medoids(&locations, target_count).iter().map(|i| towers[*i].id).collect(),
+        TowerSelection::GreedyCoverage => {
+            let demand: Vec<(f64, f64)> = cell_data.trips.values().flatten()
+                .flat_map(|t| t.trip.shape_points.iter().map(|p| (p.shape_pt_lat, p.shape_pt_lon)))
+                .collect();
+            greedy_coverage(&towers, &demand, target_count)
+        }
+    };
+    println!("Selected {} of {} radio cells", selected.len(), cell_data.radio_cells.len());
+
+    let mut remaining: HashMap<CellGlobalId, RadioCell> = selected.iter().map(|id| (*id, cell_data.radio_cells[id].clone())).collect();
+    for trip in cell_data.trips.values_mut().flatten() {
+        let mut shape_id_to_cell_id = HashMap::new();
+        find_closest_towers(&trip.trip.shape_points, &mut remaining, &mut shape_id_to_cell_id);
+        trip.cell_data = shape_id_to_cell_id;
+    }
+    cell_data.radio_cells = remaining;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_exactly_k_locations() {
+        let locations = vec![(52.50, 13.40), (52.51, 13.40), (52.50, 13.45), (52.55, 13.30), (52.45, 13.50), (52.52, 13.41)];
+        for k in 1..=locations.len() {
+            for mut selected in [k_means(&locations, k), k_medoids(&locations, k)] {
+                selected.sort();
+                selected.dedup();
+                assert_eq!(selected.len(), k);
+            }
+        }
+    }
+
+    #[test]
+    fn test_select_co_located_cells() {
+        //three cells of the same site and one cell further away
+        let locations = vec![(52.5, 13.4), (52.5, 13.4), (52.5, 13.4), (52.6, 13.4)];
+        assert_eq!(farthest_point_seeds(&locations, 3), vec![0, 3, 1]);
+        assert_eq!(farthest_point_seeds(&locations, 5), vec![0, 3, 1, 2]);
+        for mut selected in [k_means(&locations, 3), k_medoids(&locations, 3)] {
+            selected.sort();
+            assert_eq!(selected.len(), 3);
+            assert!(selected.contains(&3));
+            selected.dedup();
+            assert_eq!(selected.len(), 3);
+        }
+    }
+}